@@ -0,0 +1,102 @@
+//! aliases are "alias dir" => "dir that has the list"
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    COL_SEP_CH, Error, Result,
+    files::{read_file, with_pushed, write_atomic},
+};
+
+pub(crate) const ALIASES_NAME: &str = "aliases.tsv";
+
+/// Other spellings of directories that have a todo list, for the cases
+/// canonical keys don't catch (bind mounts, case-insensitive filesystems).
+///
+/// Aliases always point straight at the directory with the list, never at
+/// another alias.
+#[derive(Debug, Default)]
+pub(crate) struct Aliases {
+    /// (alias, dir)
+    entries: Vec<(String, String)>,
+}
+
+impl Aliases {
+    pub(crate) fn load(store_dir: &mut PathBuf) -> Result<Self> {
+        let buf = read_file(store_dir, ALIASES_NAME)?;
+        with_pushed(store_dir, ALIASES_NAME, |path| Self::parse(&buf, path))
+    }
+
+    /// Loads the aliases like `load`, but hands the index and content of
+    /// lines that can't be parsed to `malformed`, which skips them by
+    /// returning `Ok`
+    pub(crate) fn load_with(
+        store_dir: &mut PathBuf,
+        malformed: impl FnMut(usize, &str) -> Result<()>,
+    ) -> Result<Self> {
+        let buf = read_file(store_dir, ALIASES_NAME)?;
+        Self::parse_with(&buf, malformed)
+    }
+
+    fn parse(buf: &str, path: &Path) -> Result<Self> {
+        Self::parse_with(buf, |line_idx, line| {
+            Err(Error::corrupt_record(path, line_idx, line))
+        })
+    }
+
+    fn parse_with(buf: &str, mut malformed: impl FnMut(usize, &str) -> Result<()>) -> Result<Self> {
+        let mut entries = Vec::new();
+        for (line_idx, line) in buf.lines().enumerate() {
+            match line.split_once(COL_SEP_CH) {
+                Some((alias, dir)) if !dir.contains(COL_SEP_CH) => {
+                    entries.push((alias.to_string(), dir.to_string()))
+                }
+                _ => malformed(line_idx, line)?,
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    pub(crate) fn save(&self, store_dir: &mut PathBuf) -> Result<()> {
+        use std::fmt::Write as _;
+        let mut buf = String::with_capacity(self.entries.len() * 128);
+        for (alias, dir) in self.iter() {
+            writeln!(&mut buf, "{alias}{COL_SEP_CH}{dir}").unwrap();
+        }
+        write_atomic(store_dir, ALIASES_NAME, buf.as_bytes())
+    }
+
+    /// The directory `dir` is an alias of, else `dir` itself
+    pub(crate) fn resolve<'a>(&'a self, dir: &'a str) -> &'a str {
+        self.entries
+            .iter()
+            .find(|(alias, _dir)| alias == dir)
+            .map_or(dir, |(_alias, dir)| dir.as_str())
+    }
+
+    /// Points `alias` at `dir`, replacing what it pointed at before. Aliases
+    /// of `alias` are moved over to `dir`.
+    pub(crate) fn insert(&mut self, alias: &str, dir: &str) {
+        self.entries.retain(|(a, _d)| a != alias);
+        self.retarget(alias, dir);
+        self.entries.push((alias.to_string(), dir.to_string()));
+    }
+
+    /// Points the aliases of `from` at `to`
+    pub(crate) fn retarget(&mut self, from: &str, to: &str) {
+        for (_a, d) in self.entries.iter_mut() {
+            if d == from {
+                *d = to.to_string();
+            }
+        }
+    }
+
+    /// Removes `alias`, returning the directory it pointed at
+    pub(crate) fn remove(&mut self, alias: &str) -> Option<String> {
+        let idx = self.entries.iter().position(|(a, _d)| a == alias)?;
+        Some(self.entries.remove(idx).1)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(a, d)| (a.as_str(), d.as_str()))
+    }
+}
@@ -0,0 +1,71 @@
+//! archive is "archived at" "dir" then the record, one todo per line
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    COL_SEP_CH, Error, Record, Result,
+    files::{read_file, with_pushed, write_atomic},
+    record::{read_lines_with, write_header},
+};
+
+const ARCHIVE_NAME: &str = "archive.tsv";
+
+/// A todo taken out of its directory's list and kept in the store's archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Archived {
+    /// Seconds since the Unix epoch
+    pub archived_at: u64,
+    pub dir: String,
+    pub record: Record,
+}
+
+/// Every archived todo, oldest first
+pub(crate) fn load(store_dir: &mut PathBuf) -> Result<Vec<Archived>> {
+    let raw = read_file(store_dir, ARCHIVE_NAME)?;
+    with_pushed(store_dir, ARCHIVE_NAME, |path| parse(&raw, path))
+}
+
+fn parse(raw: &str, path: &Path) -> Result<Vec<Archived>> {
+    let mut archived = Vec::new();
+    read_lines_with(
+        raw,
+        path,
+        |_key, _value| None,
+        |line, layout| {
+            let (archived_at, rest) = line.split_once(COL_SEP_CH)?;
+            let (dir, record) = rest.split_once(COL_SEP_CH)?;
+            archived.push(Archived {
+                archived_at: archived_at.parse().ok()?,
+                dir: dir.to_string(),
+                record: Record::parse_line(record, layout)?,
+            });
+            Some(())
+        },
+        |line_idx, line| Err(Error::corrupt_record(path, line_idx, line)),
+    )?;
+    Ok(archived)
+}
+
+/// Replaces the archive with `archived`
+pub(crate) fn save(store_dir: &mut PathBuf, archived: &[Archived]) -> Result<()> {
+    use std::fmt::Write as _;
+    let mut buf = String::with_capacity(archived.len() * 96 + 64);
+    write_header(&mut buf);
+    for entry in archived {
+        write!(
+            &mut buf,
+            "{}{COL_SEP_CH}{}{COL_SEP_CH}",
+            entry.archived_at, entry.dir
+        )
+        .unwrap();
+        entry.record.write_line(&mut buf);
+    }
+    write_atomic(store_dir, ARCHIVE_NAME, buf.as_bytes())
+}
+
+/// Adds `new` to the end of the archive
+pub(crate) fn append(store_dir: &mut PathBuf, new: Vec<Archived>) -> Result<()> {
+    let mut archived = load(store_dir)?;
+    archived.extend(new);
+    save(store_dir, &archived)
+}
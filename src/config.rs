@@ -0,0 +1,89 @@
+//! config is "key" => "value"
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    COL_SEP_CH, Error, Result, Scope,
+    files::{read_file, with_pushed, write_atomic},
+};
+
+const CONFIG_NAME: &str = "config.tsv";
+const LIST_UP_KEY: &str = "list.up";
+const SCOPE_KEY: &str = "scope";
+const ARCHIVE_MAX_AGE_KEY: &str = "archive.max_age_days";
+
+/// Settings of a store, set with `todo config`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// `list.up`: `todo list` also shows the todos of parent directories
+    pub list_up: bool,
+    /// `scope`: whose todo list commands use when `--scope` isn't given
+    pub scope: Scope,
+    /// `archive.max_age_days`: `todo gc` drops archived todos older than
+    /// this, 0 keeps them
+    pub archive_max_age_days: u64,
+}
+
+impl Config {
+    /// Every setting, in the order they are listed
+    pub const KEYS: &[&str] = &[LIST_UP_KEY, SCOPE_KEY, ARCHIVE_MAX_AGE_KEY];
+
+    pub(crate) fn load(store_dir: &mut PathBuf) -> Result<Self> {
+        let buf = read_file(store_dir, CONFIG_NAME)?;
+        with_pushed(store_dir, CONFIG_NAME, |path| Self::parse(&buf, path))
+    }
+
+    fn parse(buf: &str, path: &Path) -> Result<Self> {
+        let mut config = Config::default();
+        for (line_idx, line) in buf.lines().enumerate() {
+            let parsed = line
+                .split_once(COL_SEP_CH)
+                .is_some_and(|(key, value)| config.set(key, value).is_ok());
+            if !parsed {
+                return Err(Error::corrupt_record(path, line_idx, line));
+            }
+        }
+        Ok(config)
+    }
+
+    pub(crate) fn save(&self, store_dir: &mut PathBuf) -> Result<()> {
+        use std::fmt::Write as _;
+        let mut buf = String::new();
+        for key in Self::KEYS {
+            let value = self.get(key).unwrap_or_default();
+            writeln!(&mut buf, "{key}{COL_SEP_CH}{value}").unwrap();
+        }
+        write_atomic(store_dir, CONFIG_NAME, buf.as_bytes())
+    }
+
+    /// Value of the setting `key` as `todo config` shows it
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key {
+            LIST_UP_KEY => Some(self.list_up.to_string()),
+            SCOPE_KEY => Some(self.scope.to_string()),
+            ARCHIVE_MAX_AGE_KEY => Some(self.archive_max_age_days.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Sets `key` from its textual `value`
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            LIST_UP_KEY => self.list_up = parse_bool(key, value)?,
+            SCOPE_KEY => self.scope = value.parse().map_err(Error::InvalidConfig)?,
+            ARCHIVE_MAX_AGE_KEY => {
+                self.archive_max_age_days = value.parse().map_err(|_| {
+                    Error::InvalidConfig(format!("{key} is a number of days, not \"{value}\""))
+                })?
+            }
+            _ => return Err(Error::InvalidConfig(format!("unknown setting \"{key}\""))),
+        }
+        Ok(())
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    value
+        .parse()
+        .map_err(|_| Error::InvalidConfig(format!("{key} is true or false, not \"{value}\"")))
+}
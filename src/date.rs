@@ -0,0 +1,392 @@
+//! Dates are whole days in the local time zone
+
+use std::{fmt, str::FromStr};
+
+use crate::files::unix_now;
+
+const SECS_PER_DAY: u64 = 86_400;
+/// Weekday names from Monday, matched by their first three letters too
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// Days since 1970-01-01 of 0000-01-01 and 9999-12-31, the dates todo
+/// files can hold
+const MIN_DAYS: i64 = -719_528;
+const MAX_DAYS: i64 = 2_932_896;
+
+/// A calendar day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    /// Days since 1970-01-01
+    days: i64,
+}
+
+impl Date {
+    /// The date, `None` if it doesn't exist or is outside years 0000 to 9999
+    pub fn from_ymd(year: i64, month: u32, day: u32) -> Option<Self> {
+        if !(0..=9999).contains(&year)
+            || !(1..=12).contains(&month)
+            || day == 0
+            || day > days_in_month(year, month)
+        {
+            return None;
+        }
+        // Howard Hinnant's days_from_civil
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let month = i64::from(month);
+        let day_of_year =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        Some(Self {
+            days: era * 146_097 + day_of_era - 719_468,
+        })
+    }
+
+    /// (year, month, day)
+    pub fn ymd(self) -> (i64, u32, u32) {
+        // Howard Hinnant's civil_from_days
+        let days = self.days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+
+    pub fn today() -> Self {
+        Self::from_unix(unix_now())
+    }
+
+    /// The local day of `secs` seconds since the Unix epoch
+    pub fn from_unix(secs: u64) -> Self {
+        Self::from_unix_with_offset(secs, utc_offset(secs))
+    }
+
+    /// The day of `secs` seconds since the Unix epoch in a time zone
+    /// `offset` seconds ahead of UTC
+    fn from_unix_with_offset(secs: u64, offset: i64) -> Self {
+        let local = i64::try_from(secs)
+            .unwrap_or(i64::MAX)
+            .saturating_add(offset);
+        Self {
+            days: local.div_euclid(SECS_PER_DAY as i64).min(MAX_DAYS),
+        }
+    }
+
+    /// `days` later, held to the years 0000 to 9999
+    pub fn add_days(self, days: i64) -> Self {
+        Self {
+            days: self.days.saturating_add(days).clamp(MIN_DAYS, MAX_DAYS),
+        }
+    }
+
+    /// `days` later, `None` past the years 0000 to 9999
+    fn checked_add_days(self, days: i64) -> Option<Self> {
+        let days = self.days.checked_add(days)?;
+        (MIN_DAYS..=MAX_DAYS)
+            .contains(&days)
+            .then_some(Self { days })
+    }
+
+    /// Days from `self` to `other`, negative if `other` is earlier
+    pub fn days_until(self, other: Date) -> i64 {
+        other.days - self.days
+    }
+
+    /// The same day `months` months later, or the last day of that month if
+    /// it is shorter
+    fn add_months(self, months: i64) -> Option<Self> {
+        let (year, month, day) = self.ymd();
+        let month_index = (year * 12 + i64::from(month) - 1).checked_add(months)?;
+        let (year, month) = (
+            month_index.div_euclid(12),
+            month_index.rem_euclid(12) as u32 + 1,
+        );
+        Self::from_ymd(year, month, day.min(days_in_month(year, month)))
+    }
+
+    /// 0 for Monday to 6 for Sunday
+    fn weekday(self) -> i64 {
+        // 1970-01-01 was a Thursday
+        (self.days + 3).rem_euclid(7)
+    }
+
+    /// Parses `YYYY-MM-DD`, `today`, `tomorrow`, a weekday (the next one
+    /// after `today`) or a count of days, weeks or months from `today` like
+    /// `3d`, `2w`, `1m` or `in 2 weeks`
+    pub fn parse(s: &str, today: Date) -> Option<Self> {
+        Self::parse_toward(s, today, 1)
+    }
+
+    /// Parses like [`Date::parse`] but looking back from `today`:
+    /// `yesterday`, a weekday is the last one before `today` and `3d`, `2w`,
+    /// `1m` or `2 weeks ago` count back
+    pub fn parse_past(s: &str, today: Date) -> Option<Self> {
+        Self::parse_toward(s, today, -1)
+    }
+
+    /// `direction` is 1 to count forward from `today` and -1 to count back
+    fn parse_toward(s: &str, today: Date, direction: i64) -> Option<Self> {
+        let s = s.trim().to_ascii_lowercase();
+        if let Ok(date) = s.parse() {
+            return Some(date);
+        }
+        match (s.as_str(), direction) {
+            ("today", _) => return Some(today),
+            ("tomorrow", 1) | ("yesterday", -1) => return today.checked_add_days(direction),
+            _ => {}
+        }
+        if let Some(weekday) = WEEKDAYS
+            .iter()
+            .position(|name| s.len() >= 3 && name.starts_with(s.as_str()))
+        {
+            let days_away = (direction * (weekday as i64 - today.weekday()) - 1).rem_euclid(7) + 1;
+            return today.checked_add_days(direction * days_away);
+        }
+        let offset = match direction {
+            1 => s.strip_prefix("in "),
+            _ => s.strip_suffix(" ago"),
+        };
+        let offset = offset.unwrap_or(&s);
+        let digits = offset.bytes().take_while(u8::is_ascii_digit).count();
+        let count = offset[..digits]
+            .parse::<i64>()
+            .ok()?
+            .checked_mul(direction)?;
+        match offset[digits..].trim() {
+            "d" | "day" | "days" => today.checked_add_days(count),
+            "w" | "week" | "weeks" => today.checked_add_days(count.checked_mul(7)?),
+            "m" | "month" | "months" => today.add_months(count),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        write!(f, "{year:04}-{month:02}-{day:02}")
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    /// Parses `YYYY-MM-DD` only, see [`Date::parse`] for the rest
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        parse_iso(s).ok_or_else(|| format!("\"{s}\" is not a date like 2026-11-01"))
+    }
+}
+
+fn parse_iso(s: &str) -> Option<Date> {
+    let mut parts = s.split('-');
+    let mut number = || {
+        parts
+            .next()
+            .filter(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+    };
+    let (year, month, day) = (number()?, number()?, number()?);
+    if year.len() != 4 || parts.next().is_some() {
+        return None;
+    }
+    Date::from_ymd(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)
+}
+
+/// Seconds the local time zone is ahead of UTC at `secs` seconds since the
+/// Unix epoch, 0 if the C library can't tell
+#[cfg(unix)]
+fn utc_offset(secs: u64) -> i64 {
+    let Ok(time) = libc::time_t::try_from(secs) else {
+        return 0;
+    };
+    // SAFETY: tm is plain integers (and a pointer on some systems) for which
+    // all zeroes is valid, localtime_r only writes to it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call, and
+    // unlike localtime, localtime_r doesn't share its result between threads
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    // a c_long, which is only 32 bits on some systems
+    #[allow(clippy::useless_conversion)]
+    i64::from(tm.tm_gmtoff)
+}
+
+/// Without a C library to ask, days are in UTC
+#[cfg(not(unix))]
+fn utc_offset(_secs: u64) -> i64 {
+    0
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> Date {
+        s.parse().unwrap()
+    }
+
+    /// A Saturday
+    fn today() -> Date {
+        date("2026-10-17")
+    }
+
+    #[test]
+    fn range_ends() {
+        assert_eq!(Date::from_ymd(0, 1, 1).unwrap().days, MIN_DAYS);
+        assert_eq!(Date::from_ymd(9999, 12, 31).unwrap().days, MAX_DAYS);
+        assert_eq!(Date::from_ymd(10_000, 1, 1), None);
+        assert_eq!(Date::from_ymd(-1, 12, 31), None);
+    }
+
+    #[test]
+    fn ymd_round_trip() {
+        for s in [
+            "1970-01-01",
+            "2000-02-29",
+            "2026-12-31",
+            "0000-01-01",
+            "9999-12-31",
+        ] {
+            assert_eq!(date(s).to_string(), s);
+        }
+        for bad in [
+            "2026-1-1x",
+            "26-01-01",
+            "2026-00-01",
+            "2026-04-31",
+            "2026-01",
+        ] {
+            assert!(bad.parse::<Date>().is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn leap_years() {
+        assert!("2024-02-29".parse::<Date>().is_ok());
+        assert!("2000-02-29".parse::<Date>().is_ok());
+        assert!("2026-02-29".parse::<Date>().is_err());
+        assert!("1900-02-29".parse::<Date>().is_err());
+        assert_eq!(date("2024-02-28").add_days(1), date("2024-02-29"));
+    }
+
+    #[test]
+    fn relative_days_and_weeks() {
+        let parse = |s| Date::parse(s, today());
+        assert_eq!(parse("today"), Some(today()));
+        assert_eq!(parse("Tomorrow"), Some(date("2026-10-18")));
+        assert_eq!(parse("3d"), Some(date("2026-10-20")));
+        assert_eq!(parse("in 2 weeks"), Some(date("2026-10-31")));
+        assert_eq!(parse("2026-11-01"), Some(date("2026-11-01")));
+        assert_eq!(parse("soon"), None);
+        assert_eq!(parse("yesterday"), None);
+        assert_eq!(parse("3d ago"), None);
+        assert_eq!(today().days_until(date("2026-10-20")), 3);
+    }
+
+    #[test]
+    fn relative_days_ago() {
+        let parse = |s| Date::parse_past(s, today());
+        assert_eq!(parse("yesterday"), Some(date("2026-10-16")));
+        assert_eq!(parse("3d ago"), Some(date("2026-10-14")));
+        assert_eq!(parse("2 weeks ago"), Some(date("2026-10-03")));
+        assert_eq!(parse("1m"), Some(date("2026-09-17")));
+        assert_eq!(parse("tomorrow"), None);
+        assert_eq!(parse("in 3d"), None);
+    }
+
+    #[test]
+    fn weekdays_wrap_around() {
+        // the same weekday as today is a week away either way
+        assert_eq!(Date::parse("saturday", today()), Some(date("2026-10-24")));
+        assert_eq!(Date::parse("mon", today()), Some(date("2026-10-19")));
+        assert_eq!(Date::parse("friday", today()), Some(date("2026-10-23")));
+        assert_eq!(Date::parse_past("sat", today()), Some(date("2026-10-10")));
+        assert_eq!(
+            Date::parse_past("sunday", today()),
+            Some(date("2026-10-11"))
+        );
+        assert_eq!(
+            Date::parse_past("friday", today()),
+            Some(date("2026-10-16"))
+        );
+        assert_eq!(Date::parse("fr", today()), None);
+    }
+
+    #[test]
+    fn months_clamp_to_month_end() {
+        assert_eq!(
+            Date::parse("1m", date("2026-01-31")),
+            Some(date("2026-02-28"))
+        );
+        assert_eq!(
+            Date::parse("1m", date("2024-01-31")),
+            Some(date("2024-02-29"))
+        );
+        assert_eq!(
+            Date::parse("2m", date("2026-12-31")),
+            Some(date("2027-02-28"))
+        );
+        assert_eq!(
+            Date::parse_past("1m", date("2026-03-31")),
+            Some(date("2026-02-28"))
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(Date::parse("10000000w", today()), None);
+        assert_eq!(Date::parse("9223372036854775807d", today()), None);
+        assert_eq!(Date::parse("9223372036854775807m", today()), None);
+        assert_eq!(Date::parse("99999999999999999999d", today()), None);
+        assert_eq!(Date::parse_past("3000000d", today()), None);
+        assert_eq!(Date::parse("10000-01-01", today()), None);
+        assert_eq!(Date::parse("2026-13-01", today()), None);
+        assert_eq!(Date::parse("1d", date("9999-12-31")), None);
+        assert_eq!(date("9999-12-31").add_days(i64::MAX), date("9999-12-31"));
+    }
+
+    #[test]
+    fn days_follow_the_time_zone() {
+        let secs = 1_792_195_200; // 2026-10-17 00:00 UTC
+        assert_eq!(Date::from_unix_with_offset(secs, 0), today());
+        assert_eq!(Date::from_unix_with_offset(secs - 1, 0), date("2026-10-16"));
+        // 20:00 the evening before in New York, 09:00 in Tokyo
+        assert_eq!(
+            Date::from_unix_with_offset(secs, -4 * 3600),
+            date("2026-10-16")
+        );
+        assert_eq!(Date::from_unix_with_offset(secs, 9 * 3600), today());
+        assert_eq!(Date::from_unix_with_offset(secs - 3600, 9 * 3600), today());
+        assert_eq!(Date::from_unix_with_offset(u64::MAX, 0), date("9999-12-31"));
+        // every time zone is within a day of UTC
+        assert!(utc_offset(secs).abs() < SECS_PER_DAY as i64);
+    }
+}
@@ -0,0 +1,170 @@
+//! dir map is "path" => "hash of path"
+
+use std::path::Path;
+
+use crate::{
+    COL_SEP_CH, Error, Result,
+    record::{FORMAT_META, META_PREFIX, parse_format},
+};
+
+pub(crate) const DIR_MAP_NAME: &str = "dirmap.tsv";
+/// Version of the dir map format written. Version 1 has no `#format` line,
+/// keys never start with `#` so the line can't be mistaken for an entry.
+pub(crate) const DIR_MAP_FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub(crate) struct DirMap {
+    /// Version the dir map was read in
+    pub(crate) format: u32,
+    /// (dir, todo file name)
+    entries: Vec<(String, String)>,
+}
+
+impl Default for DirMap {
+    fn default() -> Self {
+        Self {
+            format: DIR_MAP_FORMAT_VERSION,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl DirMap {
+    /// Parses the dir map read from `path`
+    pub(crate) fn parse(dir_map_buf: &str, path: &Path) -> Result<Self> {
+        Self::parse_with(dir_map_buf, path, |line_idx, line| {
+            Err(Error::corrupt_record(path, line_idx, line))
+        })
+    }
+
+    /// Parses the dir map like `parse`, but hands the index and content of
+    /// lines that can't be parsed to `malformed`, which skips them by
+    /// returning `Ok`
+    pub(crate) fn parse_with(
+        dir_map_buf: &str,
+        path: &Path,
+        mut malformed: impl FnMut(usize, &str) -> Result<()>,
+    ) -> Result<Self> {
+        let mut format = if dir_map_buf.is_empty() {
+            DIR_MAP_FORMAT_VERSION
+        } else {
+            1
+        };
+        let mut entries = Vec::new();
+        for (line_idx, line) in dir_map_buf.lines().enumerate() {
+            // key (dir) => value (todo file name)
+            let parsed = match line.split_once(COL_SEP_CH) {
+                Some((key, value)) if key.strip_prefix(META_PREFIX) == Some(FORMAT_META) => {
+                    parse_format(value, path, DIR_MAP_FORMAT_VERSION)
+                        .transpose()?
+                        .map(|version| format = version)
+                }
+                Some((dir, file_name))
+                    if !dir.starts_with(META_PREFIX) && !file_name.contains(COL_SEP_CH) =>
+                {
+                    entries.push((dir.to_string(), file_name.to_string()));
+                    Some(())
+                }
+                _ => None,
+            };
+            if parsed.is_none() {
+                malformed(line_idx, line)?;
+            }
+        }
+        Ok(Self { format, entries })
+    }
+
+    /// Name of the todo file of `dir`
+    pub(crate) fn file_name(&self, dir: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _v)| k == dir)
+            .map(|(_k, v)| v.as_str())
+    }
+
+    pub(crate) fn insert(&mut self, dir: &str, file_name: String) {
+        self.entries.push((dir.to_string(), file_name));
+    }
+
+    /// Moves the entry of `from` to the key `to` and its list to `file_name`
+    pub(crate) fn rekey(&mut self, from: &str, to: &str, file_name: String) {
+        if let Some(entry) = self.entries.iter_mut().find(|(k, _v)| k == from) {
+            *entry = (to.to_string(), file_name);
+        }
+    }
+
+    /// Removes the entry of `dir`, returning its file name
+    pub(crate) fn remove(&mut self, dir: &str) -> Option<String> {
+        let idx = self.entries.iter().position(|(k, _v)| k == dir)?;
+        Some(self.entries.remove(idx).1)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (&str, &mut String)> {
+        self.entries.iter_mut().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub(crate) fn to_tsv(&self) -> String {
+        use std::fmt::Write as _;
+        let mut buf = String::with_capacity(self.entries.len() * 64 + 16);
+        writeln!(
+            &mut buf,
+            "{META_PREFIX}{FORMAT_META}{COL_SEP_CH}{DIR_MAP_FORMAT_VERSION}"
+        )
+        .unwrap();
+        for (dir, file_name) in self.iter() {
+            writeln!(&mut buf, "{dir}{COL_SEP_CH}{file_name}").unwrap();
+        }
+        buf
+    }
+}
+
+/// 64-bit FNV-1a of `bytes`.
+///
+/// The todo file names are derived from this so it must never change; unlike
+/// `DefaultHasher` its output is fixed by the algorithm, not the Rust release.
+fn calculate_hash(bytes: &[u8]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Name of the todo file for the directory `dir`: `<hex FNV-1a of dir>.tsv`
+pub(crate) fn todo_file_name(dir: &str) -> String {
+    format!("{:016x}.tsv", calculate_hash(dir.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Existing stores find their files by these names
+    #[test]
+    fn todo_file_names_are_fixed() {
+        assert_eq!(todo_file_name(""), "cbf29ce484222325.tsv");
+        assert_eq!(todo_file_name("a"), "af63dc4c8601ec8c.tsv");
+        assert_eq!(todo_file_name("/home/user/project"), "f8a71a04e8340307.tsv");
+    }
+
+    #[test]
+    fn rejects_lines_without_one_tab() {
+        let path = Path::new(DIR_MAP_NAME);
+        let map = DirMap::parse("/a\ta.tsv\n/b\tb.tsv\n", path).unwrap();
+        assert_eq!(map.file_name("/b"), Some("b.tsv"));
+        assert_eq!(
+            DirMap::parse(&map.to_tsv(), path).unwrap().file_name("/a"),
+            Some("a.tsv")
+        );
+        for raw in ["/a\ta.tsv\nno tab\n", "/a\ta.tsv\textra\n"] {
+            assert!(matches!(
+                DirMap::parse(raw, path),
+                Err(Error::CorruptRecord { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::Backend;
+
+/// Everything that can stop a todo command.
+///
+/// Each variant maps to its own process exit code (see [`Error::exit_code`])
+/// so scripts can tell them apart. Exit code 1 is left to argh for bad usage.
+#[derive(Debug)]
+pub enum Error {
+    /// No todo list, or no record with the requested ID, in this directory
+    NotFound(String),
+    /// A todo with the same text already exists at `id`
+    AlreadyExists { text: String, id: u64 },
+    /// The user declined a confirmation prompt
+    Cancelled,
+    /// Another process held the store lock for longer than `timeout`
+    LockTimeout { path: PathBuf, timeout: Duration },
+    /// A line of the dir map or a todo file can't be parsed
+    CorruptRecord {
+        path: PathBuf,
+        line: usize,
+        content: String,
+    },
+    /// The file at `path` was written by a newer version of todo
+    UnsupportedVersion { path: PathBuf, version: u32 },
+    /// A setting name or value `todo config` doesn't accept
+    InvalidConfig(String),
+    /// `todo fsck` found problems it didn't repair
+    Damaged { problems: usize },
+    /// The home directory (where the store lives) is unknown
+    NoHomeDir,
+    /// Reading or writing `path` failed
+    Io { path: PathBuf, source: io::Error },
+    /// The store is not in a state that allows the operation
+    Conflict(String),
+    /// A change was asked of a store opened with `Access::Read`
+    ReadOnly,
+    /// The store uses a backend this build doesn't include
+    BackendUnavailable(Backend),
+    /// The SQLite database at `path` failed
+    #[cfg(feature = "sqlite")]
+    Sqlite {
+        path: PathBuf,
+        source: rusqlite::Error,
+    },
+}
+
+impl Error {
+    /// Process exit code for the error, documented in the README
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::NotFound(_) => 2,
+            Error::AlreadyExists { .. } => 3,
+            Error::Cancelled => 5,
+            Error::LockTimeout { .. } => 6,
+            Error::CorruptRecord { .. } => 7,
+            Error::NoHomeDir => 8,
+            Error::UnsupportedVersion { .. } => 14,
+            Error::Damaged { .. } => 15,
+            Error::InvalidConfig(_) => 16,
+            Error::ReadOnly => 17,
+            Error::Io { .. } => 10,
+            Error::Conflict(_) => 11,
+            Error::BackendUnavailable(_) => 12,
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite { .. } => 13,
+        }
+    }
+
+    /// Wraps an I/O error on `path`, for `map_err`
+    pub fn io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Error {
+        move |source| Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub(crate) fn corrupt_record(path: &Path, line_idx: usize, line: &str) -> Error {
+        Error::CorruptRecord {
+            path: path.to_path_buf(),
+            line: line_idx + 1,
+            content: line.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(what) => write!(f, "{what}"),
+            Error::AlreadyExists { text, id } => {
+                write!(f, "the todo: \"{text}\" already exists at id: {id}")
+            }
+            Error::Cancelled => write!(f, "canceled"),
+            Error::LockTimeout { path, timeout } => write!(
+                f,
+                "timed out after {}s waiting for the lock on {path:?}; another todo is using the store",
+                timeout.as_secs()
+            ),
+            Error::CorruptRecord {
+                path,
+                line,
+                content,
+            } => write!(f, "malformed record at {path:?} line {line}: \"{content}\""),
+            Error::UnsupportedVersion { path, version } => write!(
+                f,
+                "{path:?} has format version {version}, which needs a newer version of todo"
+            ),
+            Error::InvalidConfig(what) => write!(f, "{what}"),
+            Error::Damaged { problems } => {
+                write!(f, "the store has {problems} unrepaired problems")
+            }
+            Error::NoHomeDir => write!(f, "can't find the home directory"),
+            Error::Io { path, source } => write!(f, "{path:?}: {source}"),
+            Error::Conflict(what) => write!(f, "{what}"),
+            Error::ReadOnly => write!(f, "the todo store was opened read only"),
+            Error::BackendUnavailable(backend) => write!(
+                f,
+                "the store uses the {backend} backend, which this build of todo doesn't include"
+            ),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite { path, source } => write!(f, "{path:?}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
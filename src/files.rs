@@ -0,0 +1,156 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError, read_to_string, rename},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Error, Result, store::Access};
+
+const NEW_FILE_EXT: &str = "new";
+const LOCK_NAME: &str = "lock";
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub(crate) fn with_pushed<P, F, Out>(buf: &mut PathBuf, to_push: P, mut f: F) -> Out
+where
+    P: AsRef<Path>,
+    F: FnMut(&Path) -> Out,
+{
+    buf.push(to_push);
+    let out = f(buf.as_path());
+    buf.pop();
+    out
+}
+
+/// Replaces `file_name` in `todo_dir` with `contents` so a crash leaves either
+/// the old or the new file, never a partial one: the contents go to a temp
+/// sibling which is fsynced, renamed over the target and the rename fsynced.
+pub(crate) fn write_atomic(todo_dir: &mut PathBuf, file_name: &str, contents: &[u8]) -> Result<()> {
+    let path = with_pushed(todo_dir, file_name, |path| path.to_path_buf());
+    let tmp_path = path.with_added_extension(NEW_FILE_EXT);
+    {
+        // truncate, a temp file left behind by a crash is stale
+        let mut handle = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .map_err(Error::io(&tmp_path))?;
+        handle.write_all(contents).map_err(Error::io(&tmp_path))?;
+        handle.sync_all().map_err(Error::io(&tmp_path))?;
+    }
+    rename(&tmp_path, &path).map_err(Error::io(&path))?;
+    sync_dir(todo_dir).map_err(Error::io(todo_dir.as_path()))
+}
+
+/// `path` with `.<ext>` added, or `.<n>.<ext>` with the lowest `n` from 2
+/// that isn't taken, so a backup never runs into an older one
+pub(crate) fn unused_path(path: &Path, ext: &str) -> PathBuf {
+    let mut candidate = path.with_added_extension(ext);
+    let mut n = 1;
+    while candidate.exists() {
+        n += 1;
+        candidate = path.with_added_extension(format!("{n}.{ext}"));
+    }
+    candidate
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Persists renames/creations in `dir`
+#[cfg(unix)]
+pub(crate) fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened as files here, the rename is as durable as it gets
+#[cfg(not(unix))]
+pub(crate) fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Reads `file_name` from `todo_dir`, a missing file reads as empty
+pub(crate) fn read_file(todo_dir: &mut PathBuf, file_name: &str) -> Result<String> {
+    with_pushed(todo_dir, file_name, |path| match read_to_string(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        other => other.map_err(Error::io(path)),
+    })
+}
+
+/// Takes an advisory lock on the store, shared for readers and exclusive for
+/// writers, retrying until `timeout` elapses.
+/// The lock is released when the returned handle is dropped.
+pub(crate) fn lock_store(
+    todo_dir: &mut PathBuf,
+    access: Access,
+    timeout: Duration,
+) -> Result<File> {
+    let lock_path = with_pushed(todo_dir, LOCK_NAME, |path| path.to_path_buf());
+    let handle = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .map_err(Error::io(&lock_path))?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let attempt = match access {
+            Access::Read => handle.try_lock_shared(),
+            Access::Write => handle.try_lock(),
+        };
+        match attempt {
+            Ok(()) => return Ok(handle),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => sleep(LOCK_POLL_INTERVAL),
+            Err(TryLockError::WouldBlock) => {
+                return Err(Error::LockTimeout {
+                    path: lock_path,
+                    timeout,
+                });
+            }
+            Err(TryLockError::Error(e)) => return Err(Error::io(lock_path)(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn atomic_writes_replace_the_whole_file() {
+        let temp = TempDir::new("atomic");
+        let mut dir = temp.path().to_path_buf();
+        // a temp file left by a crash is overwritten, not appended to
+        std::fs::write(dir.join("f.tsv.new"), "stale").unwrap();
+        write_atomic(&mut dir, "f.tsv", b"a longer first version\n").unwrap();
+        write_atomic(&mut dir, "f.tsv", b"short\n").unwrap();
+        assert_eq!(read_file(&mut dir, "f.tsv").unwrap(), "short\n");
+        assert!(!dir.join("f.tsv.new").exists());
+        assert_eq!(read_file(&mut dir, "missing.tsv").unwrap(), "");
+    }
+
+    #[test]
+    fn backups_get_an_unused_name() {
+        let temp = TempDir::new("unused");
+        let path = temp.path().join("todos.sqlite");
+        assert_eq!(
+            unused_path(&path, "bak"),
+            temp.path().join("todos.sqlite.bak")
+        );
+        std::fs::write(temp.path().join("todos.sqlite.bak"), "").unwrap();
+        std::fs::write(temp.path().join("todos.sqlite.2.bak"), "").unwrap();
+        assert_eq!(
+            unused_path(&path, "bak"),
+            temp.path().join("todos.sqlite.3.bak")
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! fsck checks the raw store files, so it works on stores `open` rejects
+
+use std::{
+    collections::HashSet,
+    fs::{OpenOptions, create_dir_all, read_dir, rename},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Error, Result,
+    alias::{ALIASES_NAME, Aliases},
+    dir_map::{DIR_MAP_NAME, DirMap},
+    files::{read_file, with_pushed, write_atomic},
+    record::{format_list, parse_list_with},
+};
+
+/// Where `fsck --repair` moves what it can't fix, inside the store
+const QUARANTINE_DIR_NAME: &str = "quarantine";
+
+/// Something wrong with the store, found by [`crate::TodoStore::fsck`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A line of a store file that can't be parsed
+    MalformedLine {
+        path: PathBuf,
+        line: usize,
+        content: String,
+    },
+    /// More than one todo of `dir` has the ID `id`
+    DuplicateId { dir: String, id: u64 },
+    /// The dir map points `dir` at a todo file that doesn't exist
+    MissingFile { dir: String, path: PathBuf },
+    /// A todo file the dir map doesn't point at
+    OrphanFile(PathBuf),
+    /// `dir` is in the dir map more than once, this later entry points at
+    /// `path`
+    DuplicateKey { dir: String, path: PathBuf },
+    /// The SQLite integrity check reported this
+    Database(String),
+}
+
+/// What `fsck --repair` did about a [`Problem`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    /// The line or entry was removed, nothing was lost
+    Removed,
+    /// The todo got this new ID
+    Renumbered(u64),
+    /// The data was moved to this file in the quarantine directory
+    Quarantined(PathBuf),
+}
+
+/// A problem and, when repairing, what was done about it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub problem: Problem,
+    /// `None` if the problem was only reported
+    pub repair: Option<Repair>,
+}
+
+/// Checks the TSV files of the store in `dir`
+pub(crate) fn check_tsv(dir: &mut PathBuf, repair: bool) -> Result<Vec<Finding>> {
+    let mut fsck = Fsck::new(dir, repair);
+    let dir_map = fsck.check_dir_map()?;
+    for (key, file_name) in dir_map.iter() {
+        fsck.check_list(key, file_name)?;
+    }
+    fsck.check_orphans()?;
+    Ok(fsck.findings)
+}
+
+/// Checks `aliases.tsv` of the store in `dir`, which every backend has
+pub(crate) fn check_aliases(dir: &mut PathBuf, repair: bool) -> Result<Vec<Finding>> {
+    let mut fsck = Fsck::new(dir, repair);
+    let mut malformed = Vec::new();
+    let aliases = Aliases::load_with(fsck.dir, |line_idx, line| {
+        malformed.push((line_idx, line.to_string()));
+        Ok(())
+    })?;
+    if fsck.report_malformed(ALIASES_NAME, malformed)? && repair {
+        aliases.save(fsck.dir)?;
+    }
+    Ok(fsck.findings)
+}
+
+struct Fsck<'a> {
+    dir: &'a mut PathBuf,
+    repair: bool,
+    /// Todo files named by the dir map, including by entries that were
+    /// dropped but whose file was left in place
+    referenced: HashSet<String>,
+    findings: Vec<Finding>,
+}
+
+impl<'a> Fsck<'a> {
+    fn new(dir: &'a mut PathBuf, repair: bool) -> Self {
+        Self {
+            dir,
+            repair,
+            referenced: HashSet::new(),
+            findings: Vec::new(),
+        }
+    }
+
+    /// The dir map without malformed lines, duplicate keys and entries whose
+    /// file is missing. When repairing, it is also written back like that.
+    fn check_dir_map(&mut self) -> Result<DirMap> {
+        let path = self.path(DIR_MAP_NAME);
+        let raw = read_file(self.dir, DIR_MAP_NAME)?;
+        let mut malformed = Vec::new();
+        let parsed = DirMap::parse_with(&raw, &path, |line_idx, line| {
+            malformed.push((line_idx, line.to_string()));
+            Ok(())
+        })?;
+        let mut changed = self.report_malformed(DIR_MAP_NAME, malformed)?;
+
+        let mut dir_map = DirMap::default();
+        for (key, file_name) in parsed.iter() {
+            self.referenced.insert(file_name.to_string());
+            let file_path = self.path(file_name);
+            if dir_map.file_name(key).is_some() {
+                let repair = match self.repair {
+                    // another entry may share the file, quarantining it would
+                    // take the todos of that entry too
+                    true if dir_map.iter().any(|(_k, f)| f == file_name) => Some(Repair::Removed),
+                    true if file_path.is_file() => Some(self.quarantine_file(file_name)?),
+                    true => Some(Repair::Removed),
+                    false => None,
+                };
+                self.push(
+                    Problem::DuplicateKey {
+                        dir: key.to_string(),
+                        path: file_path,
+                    },
+                    repair,
+                );
+                changed = true;
+            } else if !file_path.is_file() {
+                let repair = self.repair.then_some(Repair::Removed);
+                self.push(
+                    Problem::MissingFile {
+                        dir: key.to_string(),
+                        path: file_path,
+                    },
+                    repair,
+                );
+                changed = true;
+            } else {
+                dir_map.insert(key, file_name.to_string());
+            }
+        }
+        if changed && self.repair {
+            write_atomic(self.dir, DIR_MAP_NAME, dir_map.to_tsv().as_bytes())?;
+        }
+        Ok(dir_map)
+    }
+
+    /// Checks the todo file of `key`, quarantining malformed lines and giving
+    /// duplicate IDs fresh ones when repairing, or quarantining them too
+    /// once the list is out of IDs
+    fn check_list(&mut self, key: &str, file_name: &str) -> Result<()> {
+        let path = self.path(file_name);
+        let raw = read_file(self.dir, file_name)?;
+        let mut malformed = Vec::new();
+        let mut list = parse_list_with(&raw, &path, |line_idx, line| {
+            malformed.push((line_idx, line.to_string()));
+            Ok(())
+        })?;
+        let mut changed = self.report_malformed(file_name, malformed)?;
+
+        let mut seen = HashSet::new();
+        let duplicates: Vec<usize> = (0..list.records.len())
+            .filter(|idx| !seen.insert(list.records[*idx].id))
+            .collect();
+        let mut set_aside = Vec::new();
+        for idx in duplicates {
+            let id = list.records[idx].id;
+            let repair = match self.repair.then(|| list.allocate_id()) {
+                None => None,
+                Some(Some(new_id)) => {
+                    list.records[idx].id = new_id;
+                    Some(Repair::Renumbered(new_id))
+                }
+                // every ID was handed out, so the todo can only be set aside
+                Some(None) => {
+                    let mut line = String::new();
+                    list.records[idx].write_line(&mut line);
+                    set_aside.push(idx);
+                    Some(self.quarantine_lines(file_name, &[line.trim_end_matches('\n')])?)
+                }
+            };
+            self.push(
+                Problem::DuplicateId {
+                    dir: key.to_string(),
+                    id,
+                },
+                repair,
+            );
+            changed = true;
+        }
+        for idx in set_aside.into_iter().rev() {
+            list.records.remove(idx);
+        }
+        if changed && self.repair {
+            write_atomic(self.dir, file_name, format_list(&list).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reports todo files in the store the dir map doesn't point at
+    fn check_orphans(&mut self) -> Result<()> {
+        let mut orphans = Vec::new();
+        for entry in read_dir(&*self.dir).map_err(Error::io(&*self.dir))? {
+            let entry = entry.map_err(Error::io(&*self.dir))?;
+            let Ok(file_name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if is_todo_file_name(&file_name) && !self.referenced.contains(&file_name) {
+                orphans.push(file_name);
+            }
+        }
+        orphans.sort();
+        for file_name in orphans {
+            let path = self.path(&file_name);
+            let repair = match self.repair {
+                true => Some(self.quarantine_file(&file_name)?),
+                false => None,
+            };
+            self.push(Problem::OrphanFile(path), repair);
+        }
+        Ok(())
+    }
+
+    /// Reports the `malformed` lines of `file_name`, quarantining them when
+    /// repairing. Returns whether there were any.
+    fn report_malformed(
+        &mut self,
+        file_name: &str,
+        malformed: Vec<(usize, String)>,
+    ) -> Result<bool> {
+        if malformed.is_empty() {
+            return Ok(false);
+        }
+        let repair = match self.repair {
+            true => {
+                let lines: Vec<&str> = malformed.iter().map(|(_idx, l)| l.as_str()).collect();
+                Some(self.quarantine_lines(file_name, &lines)?)
+            }
+            false => None,
+        };
+        let path = self.path(file_name);
+        for (line_idx, content) in malformed {
+            self.push(
+                Problem::MalformedLine {
+                    path: path.clone(),
+                    line: line_idx + 1,
+                    content,
+                },
+                repair.clone(),
+            );
+        }
+        Ok(true)
+    }
+
+    /// Appends `lines` to the quarantined copy of `file_name`
+    fn quarantine_lines(&mut self, file_name: &str, lines: &[&str]) -> Result<Repair> {
+        let quarantine = self.quarantine_dir()?;
+        let path = quarantine.join(file_name);
+        let mut handle = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(Error::io(&path))?;
+        for line in lines {
+            writeln!(handle, "{line}").map_err(Error::io(&path))?;
+        }
+        handle.sync_all().map_err(Error::io(&path))?;
+        Ok(Repair::Quarantined(path))
+    }
+
+    /// Moves `file_name` into the quarantine directory, next to earlier
+    /// copies of the same name
+    fn quarantine_file(&mut self, file_name: &str) -> Result<Repair> {
+        let quarantine = self.quarantine_dir()?;
+        let mut target = quarantine.join(file_name);
+        let mut copy = 0;
+        while target.exists() {
+            copy += 1;
+            target = quarantine.join(format!("{file_name}.{copy}"));
+        }
+        let from = self.path(file_name);
+        rename(&from, &target).map_err(Error::io(&from))?;
+        Ok(Repair::Quarantined(target))
+    }
+
+    fn quarantine_dir(&mut self) -> Result<PathBuf> {
+        let quarantine = self.path(QUARANTINE_DIR_NAME);
+        create_dir_all(&quarantine).map_err(Error::io(&quarantine))?;
+        Ok(quarantine)
+    }
+
+    fn path(&mut self, file_name: &str) -> PathBuf {
+        with_pushed(self.dir, file_name, Path::to_path_buf)
+    }
+
+    fn push(&mut self, problem: Problem, repair: Option<Repair>) {
+        self.findings.push(Finding { problem, repair });
+    }
+}
+
+/// Whether `file_name` looks like a todo file: a hash (hex, or decimal for
+/// `DefaultHasher` names) with the `.tsv` extension
+fn is_todo_file_name(file_name: &str) -> bool {
+    file_name
+        .strip_suffix(".tsv")
+        .is_some_and(|stem| !stem.is_empty() && stem.bytes().all(|b| b.is_ascii_hexdigit()))
+}
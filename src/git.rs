@@ -0,0 +1,143 @@
+//! git support reads the few files it needs itself, it doesn't run git
+
+use std::{
+    fmt,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const DOT_GIT_NAME: &str = ".git";
+const HEAD_NAME: &str = "HEAD";
+/// Starts the `.git` file of linked worktrees and submodules
+const GITDIR_PREFIX: &str = "gitdir: ";
+const BRANCH_REF_PREFIX: &str = "ref: refs/heads/";
+/// Length of the commit hash shown for a detached HEAD
+const SHORT_HASH_LEN: usize = 7;
+
+/// Which directory a todo command uses the list of
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// The working directory
+    #[default]
+    Dir,
+    /// The root of the git worktree holding the working directory, or the
+    /// working directory outside of one
+    Git,
+}
+
+impl Scope {
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Dir => "dir",
+            Scope::Git => "git",
+        }
+    }
+
+    /// The directory whose list commands run in `dir` use
+    pub fn apply(self, dir: &Path) -> &Path {
+        match self {
+            Scope::Dir => dir,
+            Scope::Git => worktree_root(dir).unwrap_or(dir),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "dir" => Ok(Scope::Dir),
+            "git" => Ok(Scope::Git),
+            other => Err(format!("unknown scope \"{other}\", expected dir or git")),
+        }
+    }
+}
+
+/// The closest of `dir` and its ancestors holding a `.git` directory, or
+/// the `.git` file of a linked worktree
+pub fn worktree_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors()
+        .find(|ancestor| ancestor.join(DOT_GIT_NAME).exists())
+}
+
+/// Branch checked out in the worktree holding `dir`, the short commit hash
+/// when HEAD is detached. `None` outside of a worktree or if its git
+/// directory can't be read.
+pub fn current_branch(dir: &Path) -> Option<String> {
+    let root = worktree_root(dir)?;
+    let head = read_to_string(git_dir(root)?.join(HEAD_NAME)).ok()?;
+    let head = head.trim_end();
+    match head.strip_prefix(BRANCH_REF_PREFIX) {
+        Some(branch) => Some(branch.to_string()),
+        None => head.get(..SHORT_HASH_LEN).map(str::to_string),
+    }
+}
+
+/// The git directory of the worktree at `root`, following the `.git` file
+/// of linked worktrees
+fn git_dir(root: &Path) -> Option<PathBuf> {
+    let dot_git = root.join(DOT_GIT_NAME);
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let link = read_to_string(&dot_git).ok()?;
+    Some(root.join(link.trim_end().strip_prefix(GITDIR_PREFIX)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn finds_the_worktree_and_its_branch() {
+        let temp = TempDir::new("git-branch");
+        let repo = temp.path().join("repo");
+        let sub = repo.join("src").join("deep");
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::create_dir(repo.join(DOT_GIT_NAME)).unwrap();
+        std::fs::write(repo.join(".git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+
+        assert_eq!(worktree_root(&sub), Some(repo.as_path()));
+        assert_eq!(Scope::Git.apply(&sub), repo);
+        assert_eq!(Scope::Dir.apply(&sub), sub);
+        assert_eq!(current_branch(&sub).as_deref(), Some("feature/x"));
+
+        std::fs::write(repo.join(".git/HEAD"), "0123456789abcdef\n").unwrap();
+        assert_eq!(current_branch(&repo).as_deref(), Some("0123456"));
+    }
+
+    #[test]
+    fn follows_linked_worktrees() {
+        let temp = TempDir::new("git-worktree");
+        let git_dir = temp.path().join("repo/.git/worktrees/wt");
+        let worktree = temp.path().join("wt");
+        std::fs::create_dir_all(&git_dir).unwrap();
+        std::fs::create_dir_all(&worktree).unwrap();
+        std::fs::write(git_dir.join(HEAD_NAME), "ref: refs/heads/wt-branch\n").unwrap();
+        let link = format!("{GITDIR_PREFIX}{}\n", git_dir.display());
+        std::fs::write(worktree.join(DOT_GIT_NAME), link).unwrap();
+        assert_eq!(current_branch(&worktree).as_deref(), Some("wt-branch"));
+    }
+
+    #[test]
+    fn outside_a_worktree_nothing_changes() {
+        let temp = TempDir::new("git-none");
+        // the temp dir may itself be inside a worktree, so only check what
+        // doesn't depend on that
+        let broken = temp.path().join("broken");
+        std::fs::create_dir_all(broken.join(DOT_GIT_NAME)).unwrap();
+        assert_eq!(worktree_root(&broken), Some(broken.as_path()));
+        assert_eq!(current_branch(&broken), None);
+        assert_eq!("git".parse(), Ok(Scope::Git));
+        assert!("svn".parse::<Scope>().is_err());
+    }
+}
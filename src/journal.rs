@@ -0,0 +1,418 @@
+//! journal is "at" "operation" "dir" "record before" "record after", one
+//! change per line. The records are escaped whole into one column, empty
+//! for none.
+
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    path::PathBuf,
+};
+
+use crate::{
+    COL_SEP_CH, Error, Record, Result,
+    files::{read_file, sync_dir, with_pushed, write_atomic},
+    record::{escape_into, read_lines_with, unescape, write_header},
+};
+
+const JOURNAL_NAME: &str = "journal.tsv";
+/// Older entries are dropped, so they can't be undone anymore
+const JOURNAL_LEN: usize = 1000;
+/// Size in bytes past which the journal is cut back to `JOURNAL_LEN`
+/// entries, until then changes are only appended
+const COMPACT_LEN: u64 = 1 << 20;
+
+/// What a [`JournalEntry`] did to a todo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    New,
+    Update,
+    Status,
+    Priority,
+    Due,
+    /// Moved the todo to the archive
+    Delete,
+    /// Deleted the todo without archiving it. The todo is scrubbed from
+    /// the journal, so neither this nor earlier changes can bring it back.
+    Purge,
+    /// Brought the todo back from the archive
+    Restore,
+    /// Reverted an earlier entry
+    Undo,
+    /// Applied an undone entry again
+    Redo,
+}
+
+impl Operation {
+    const ALL: [Operation; 10] = [
+        Operation::New,
+        Operation::Update,
+        Operation::Status,
+        Operation::Priority,
+        Operation::Due,
+        Operation::Delete,
+        Operation::Purge,
+        Operation::Restore,
+        Operation::Undo,
+        Operation::Redo,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::New => "new",
+            Operation::Update => "update",
+            Operation::Status => "status",
+            Operation::Priority => "priority",
+            Operation::Due => "due",
+            Operation::Delete => "delete",
+            Operation::Purge => "purge",
+            Operation::Restore => "restore",
+            Operation::Undo => "undo",
+            Operation::Redo => "redo",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Operation::ALL.into_iter().find(|op| op.name() == name)
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One change to a todo, `before` is `None` for a new todo and `after` for
+/// a deleted one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Seconds since the Unix epoch
+    pub at: u64,
+    pub op: Operation,
+    pub dir: String,
+    pub before: Option<Record>,
+    pub after: Option<Record>,
+}
+
+/// Every entry in the journal, oldest first
+pub(crate) fn load(store_dir: &mut PathBuf) -> Result<Vec<JournalEntry>> {
+    let raw = read_file(store_dir, JOURNAL_NAME)?;
+    with_pushed(store_dir, JOURNAL_NAME, |path| parse(&raw, path))
+}
+
+fn parse(raw: &str, path: &Path) -> Result<Vec<JournalEntry>> {
+    let mut entries = Vec::new();
+    read_lines_with(
+        raw,
+        path,
+        |_key, _value| None,
+        |line, layout| {
+            let mut fields = line.split(COL_SEP_CH);
+            let record = |field: &str| match field {
+                "" => Some(None),
+                field => Record::parse_line(&unescape(field)?, layout).map(Some),
+            };
+            entries.push(JournalEntry {
+                at: fields.next()?.parse().ok()?,
+                op: Operation::from_name(fields.next()?)?,
+                dir: fields.next()?.to_string(),
+                before: record(fields.next()?)?,
+                after: record(fields.next()?)?,
+            });
+            fields.next().is_none().then_some(())
+        },
+        |line_idx, line| Err(Error::corrupt_record(path, line_idx, line)),
+    )?;
+    Ok(entries)
+}
+
+/// Adds `entry` to the end of the journal. Only its line is written, the
+/// whole journal is rewritten when it is past `COMPACT_LEN`, dropping the
+/// oldest entries past `JOURNAL_LEN`, or was written in an older format.
+pub(crate) fn append(store_dir: &mut PathBuf, entry: JournalEntry) -> Result<()> {
+    let mut header = String::new();
+    write_header(&mut header);
+    let mut line = String::new();
+    write_entry(&mut line, &entry);
+    let appended = with_pushed(store_dir, JOURNAL_NAME, |path| {
+        append_line(path, &header, &line).map_err(Error::io(path))
+    })?;
+    if !appended {
+        let mut entries = load(store_dir)?;
+        entries.push(entry);
+        save(store_dir, &entries)?;
+    }
+    Ok(())
+}
+
+/// Appends `line` to the journal at `path`, starting a new one with
+/// `header`. Returns `false`, writing nothing, if the journal needs to be
+/// rewritten instead.
+fn append_line(path: &Path, header: &str, line: &str) -> io::Result<bool> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    let len = file.metadata()?.len();
+    if len == 0 {
+        file.write_all(format!("{header}{line}").as_bytes())?;
+        file.sync_all()?;
+        return sync_dir(path.parent().unwrap_or(path)).map(|()| true);
+    }
+    if len > COMPACT_LEN || !starts_with(&mut file, header)? {
+        return Ok(false);
+    }
+    cut_partial_line(&mut file, len)?;
+    file.write_all(line.as_bytes())?;
+    file.sync_data().map(|()| true)
+}
+
+fn starts_with(file: &mut File, header: &str) -> io::Result<bool> {
+    let mut start = vec![0; header.len()];
+    match file.read_exact(&mut start) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        other => other.map(|()| start == header.as_bytes()),
+    }
+}
+
+/// A crash while appending can leave part of a line at the end of `file`,
+/// which is cut off so the next line doesn't run into it
+fn cut_partial_line(file: &mut File, len: u64) -> io::Result<()> {
+    let mut last = [0];
+    file.seek(SeekFrom::Start(len - 1))?;
+    file.read_exact(&mut last)?;
+    if last[0] == b'\n' {
+        return Ok(());
+    }
+    let mut raw = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut raw)?;
+    let whole = raw
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |idx| idx + 1);
+    file.set_len(whole as u64)
+}
+
+/// Blanks out every record of the todo with ID `id` of `dir`. The entries
+/// stay, so undo and redo entries still line up with those they act on.
+pub(crate) fn scrub(store_dir: &mut PathBuf, dir: &str, id: u64) -> Result<()> {
+    let mut entries = load(store_dir)?;
+    for entry in entries.iter_mut().filter(|entry| entry.dir == dir) {
+        for record in [&mut entry.before, &mut entry.after] {
+            if record.as_ref().is_some_and(|record| record.id == id) {
+                *record = None;
+            }
+        }
+    }
+    save(store_dir, &entries)
+}
+
+/// Writes the last `JOURNAL_LEN` of `entries` as the journal
+fn save(store_dir: &mut PathBuf, entries: &[JournalEntry]) -> Result<()> {
+    let kept = &entries[entries.len().saturating_sub(JOURNAL_LEN)..];
+    let mut buf = String::with_capacity(kept.len() * 128 + 64);
+    write_header(&mut buf);
+    for entry in kept {
+        write_entry(&mut buf, entry);
+    }
+    write_atomic(store_dir, JOURNAL_NAME, buf.as_bytes())
+}
+
+/// Writes the journal line of `entry` to `buf`
+fn write_entry(buf: &mut String, entry: &JournalEntry) {
+    use std::fmt::Write as _;
+    write!(
+        buf,
+        "{}{COL_SEP_CH}{}{COL_SEP_CH}{}",
+        entry.at, entry.op, entry.dir
+    )
+    .unwrap();
+    let mut line = String::new();
+    for record in [&entry.before, &entry.after] {
+        buf.push(COL_SEP_CH);
+        if let Some(record) = record {
+            line.clear();
+            record.write_line(&mut line);
+            line.pop();
+            escape_into(buf, &line);
+        }
+    }
+    buf.push('\n');
+}
+
+/// Indexes of the entries `undo` can revert and of those `redo` can apply
+/// again, the next one last. Undo and redo entries move the entry they act
+/// on from one to the other, and a purge can't be undone at all.
+pub(crate) fn undo_stacks(entries: &[JournalEntry]) -> (Vec<usize>, Vec<usize>) {
+    let (mut done, mut undone) = (Vec::new(), Vec::new());
+    for (idx, entry) in entries.iter().enumerate() {
+        match entry.op {
+            // the entries acted on may have been dropped with the oldest
+            Operation::Undo => undone.extend(done.pop()),
+            Operation::Redo => done.extend(undone.pop()),
+            Operation::Purge => undone.clear(),
+            _ => {
+                done.push(idx);
+                undone.clear();
+            }
+        }
+    }
+    (done, undone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Status, record::FORMAT_VERSION, temp_dir::TempDir};
+
+    fn entries(ops: &[Operation]) -> Vec<JournalEntry> {
+        ops.iter()
+            .map(|&op| JournalEntry {
+                at: 0,
+                op,
+                dir: "/d".to_string(),
+                before: None,
+                after: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn undo_and_redo_move_entries_between_stacks() {
+        use Operation::*;
+        assert_eq!(undo_stacks(&entries(&[New, Update])), (vec![0, 1], vec![]));
+        assert_eq!(
+            undo_stacks(&entries(&[New, Update, Undo])),
+            (vec![0], vec![1])
+        );
+        assert_eq!(
+            undo_stacks(&entries(&[New, Update, Undo, Undo])),
+            (vec![], vec![1, 0])
+        );
+        assert_eq!(
+            undo_stacks(&entries(&[New, Update, Undo, Undo, Redo])),
+            (vec![0], vec![1])
+        );
+    }
+
+    #[test]
+    fn a_new_change_drops_what_was_undone() {
+        use Operation::*;
+        assert_eq!(
+            undo_stacks(&entries(&[New, Update, Undo, Status])),
+            (vec![0, 3], vec![])
+        );
+        assert_eq!(
+            undo_stacks(&entries(&[New, Undo, Delete, Redo])),
+            (vec![2], vec![])
+        );
+    }
+
+    #[test]
+    fn undo_past_dropped_entries_does_nothing() {
+        use Operation::*;
+        // the journal lost the entries these undo and redo acted on
+        assert_eq!(undo_stacks(&entries(&[Undo, Redo, New])), (vec![2], vec![]));
+        assert_eq!(undo_stacks(&entries(&[Redo, Undo, Undo])), (vec![], vec![]));
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let record = |text: &str| Record {
+            id: 1,
+            text: text.to_string(),
+            status: Status::Active,
+            branch: Some("main".to_string()),
+            priority: "A".parse().ok(),
+            due: "2026-11-01".parse().ok(),
+            tags: vec!["+t".to_string()],
+            reason: None,
+            created_at: Some(10),
+            updated_at: Some(20),
+            completed_at: None,
+        };
+        let entry = JournalEntry {
+            at: 30,
+            op: Operation::Update,
+            dir: "/d".to_string(),
+            before: Some(record("old\ttext")),
+            after: Some(record("new\\ntext\n")),
+        };
+        let temp = TempDir::new("journal");
+        let mut dir = temp.path().to_path_buf();
+        append(&mut dir, entry.clone()).unwrap();
+        let undo = JournalEntry {
+            op: Operation::Undo,
+            before: None,
+            after: None,
+            ..entry.clone()
+        };
+        append(&mut dir, undo.clone()).unwrap();
+        assert_eq!(load(&mut dir).unwrap(), [entry, undo]);
+    }
+
+    #[test]
+    fn purges_cant_be_undone() {
+        use Operation::*;
+        assert_eq!(undo_stacks(&entries(&[New, Purge])), (vec![0], vec![]));
+        assert_eq!(
+            undo_stacks(&entries(&[New, Update, Undo, Purge, Redo])),
+            (vec![0], vec![])
+        );
+    }
+
+    #[test]
+    fn changes_are_appended_until_the_journal_is_too_long() {
+        use Operation::*;
+        let temp = TempDir::new("journal");
+        let mut dir = temp.path().to_path_buf();
+        let path = temp.path().join(JOURNAL_NAME);
+        append(&mut dir, entries(&[New]).remove(0)).unwrap();
+        let before = std::fs::read_to_string(&path).unwrap();
+        append(&mut dir, entries(&[Update]).remove(0)).unwrap();
+        let after = std::fs::read_to_string(&path).unwrap();
+        assert!(after.starts_with(&before));
+        assert_eq!(after.lines().count(), before.lines().count() + 1);
+
+        // a long journal is cut back to its last entries
+        let mut long = entries(&[New]).remove(0);
+        long.dir = "/d".repeat(COMPACT_LEN as usize / JOURNAL_LEN / 2 + 1);
+        let mut raw = String::new();
+        write_header(&mut raw);
+        for _ in 0..JOURNAL_LEN {
+            write_entry(&mut raw, &long);
+        }
+        std::fs::write(&path, raw).unwrap();
+        append(&mut dir, entries(&[Update]).remove(0)).unwrap();
+        let kept = load(&mut dir).unwrap();
+        assert_eq!(kept.len(), JOURNAL_LEN);
+        assert_eq!(kept.last().unwrap().op, Update);
+    }
+
+    #[test]
+    fn old_and_torn_journals_are_fixed_on_append() {
+        use Operation::*;
+        let temp = TempDir::new("journal");
+        let mut dir = temp.path().to_path_buf();
+        let path = temp.path().join(JOURNAL_NAME);
+        let mut raw = String::new();
+        write_header(&mut raw);
+        write_entry(&mut raw, &entries(&[New])[0]);
+
+        // an older format is rewritten in the current one
+        let old = raw.replace(&format!("\t{FORMAT_VERSION}\n"), "\t8\n");
+        std::fs::write(&path, old).unwrap();
+        append(&mut dir, entries(&[Update]).remove(0)).unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with(&raw));
+        assert_eq!(load(&mut dir).unwrap(), entries(&[New, Update]));
+
+        // a line cut short by a crash is dropped
+        std::fs::write(&path, format!("{raw}0\tupd")).unwrap();
+        append(&mut dir, entries(&[Status]).remove(0)).unwrap();
+        assert_eq!(load(&mut dir).unwrap(), entries(&[New, Status]));
+    }
+}
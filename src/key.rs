@@ -0,0 +1,176 @@
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    fs::canonicalize,
+    io::ErrorKind,
+    path::{Component, Path, PathBuf, absolute},
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+use crate::{Error, Result};
+
+/// Prefix of keys holding the path bytes in base64, for paths that aren't
+/// UTF-8 or contain characters the TSV files use as separators
+const BASE64_TAG: &str = "base64:";
+
+/// Key of the todo list of the directory `path`: absolute with symlinks, `.`
+/// and `..` resolved, so every way of reaching a directory maps to one list.
+///
+/// A path that doesn't exist (anymore) gets its longest existing ancestor
+/// resolved and the rest normalized. Any path has a key, [`key_path`] turns
+/// it back into the path.
+pub fn dir_key(path: &Path) -> Result<String> {
+    let canonical = match canonicalize(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            canonicalize_missing(&absolute(path).map_err(Error::io(path))?)?
+        }
+        other => other.map_err(Error::io(path))?,
+    };
+    Ok(path_to_key(canonical.as_os_str()))
+}
+
+/// Resolves `..` and `.` in the absolute `path` by name, then symlinks in
+/// the part of it that exists
+fn canonicalize_missing(path: &Path) -> Result<PathBuf> {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            other => normal.push(other),
+        }
+    }
+    let mut missing = Vec::new();
+    let mut existing = normal.as_path();
+    while let Some(parent) = existing.parent() {
+        if existing.exists() {
+            break;
+        }
+        missing.extend(existing.file_name());
+        existing = parent;
+    }
+    let mut canonical = canonicalize(existing).map_err(Error::io(existing))?;
+    canonical.extend(missing.iter().rev());
+    Ok(canonical)
+}
+
+/// The directory path `key` was made from
+pub fn key_path(key: &str) -> PathBuf {
+    key.strip_prefix(BASE64_TAG)
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+        .map_or_else(|| PathBuf::from(key), |bytes| bytes_to_os(bytes).into())
+}
+
+/// `key` for printing: its path, with bytes that aren't UTF-8 replaced
+pub fn display_key(key: &str) -> Cow<'_, str> {
+    if key.starts_with(BASE64_TAG) {
+        Cow::Owned(key_path(key).to_string_lossy().into_owned())
+    } else {
+        Cow::Borrowed(key)
+    }
+}
+
+/// Key of `path` as it is, without making it canonical
+pub(crate) fn path_to_key(path: &OsStr) -> String {
+    match path.to_str() {
+        // absolute paths never start with the tag, so plain keys can't be
+        // mistaken for encoded ones
+        Some(path) if !path.contains(['\t', '\n', '\r']) => path.to_string(),
+        _ => format!("{BASE64_TAG}{}", STANDARD.encode(os_to_bytes(path))),
+    }
+}
+
+#[cfg(unix)]
+fn os_to_bytes(os: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    os.as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn bytes_to_os(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+/// UTF-16 code units, little endian
+#[cfg(windows)]
+fn os_to_bytes(os: &OsStr) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt;
+    os.encode_wide().flat_map(u16::to_le_bytes).collect()
+}
+
+#[cfg(windows)]
+fn bytes_to_os(bytes: Vec<u8>) -> OsString {
+    use std::os::windows::ffi::OsStringExt;
+    let wide: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    OsString::from_wide(&wide)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn os_to_bytes(os: &OsStr) -> Vec<u8> {
+    os.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn bytes_to_os(bytes: Vec<u8>) -> OsString {
+    String::from_utf8_lossy(&bytes).into_owned().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn keys_are_canonical() {
+        let temp = TempDir::new("key-canonical");
+        let root = canonicalize(temp.path()).unwrap();
+        let real = root.join("real");
+        std::fs::create_dir(&real).unwrap();
+        let real_key = real.to_str().unwrap();
+        assert_eq!(dir_key(&real).unwrap(), real_key);
+        assert_eq!(dir_key(&root.join("real/../real/.")).unwrap(), real_key);
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&real, root.join("link")).unwrap();
+            assert_eq!(dir_key(&root.join("link")).unwrap(), real_key);
+        }
+        // gone directories keep the key they had
+        let gone = root.join("gone");
+        assert_eq!(dir_key(&gone).unwrap(), gone.to_str().unwrap());
+    }
+
+    #[test]
+    fn plain_keys_are_the_path() {
+        let key = path_to_key(OsStr::new("/home/user/project"));
+        assert_eq!(key, "/home/user/project");
+        assert_eq!(key_path(&key), Path::new("/home/user/project"));
+    }
+
+    #[test]
+    fn separators_are_encoded() {
+        let path = OsStr::new("/tmp/a\tb\nc");
+        let key = path_to_key(path);
+        assert!(key.starts_with(BASE64_TAG));
+        assert!(!key.contains(['\t', '\n', '\r']));
+        assert_eq!(key_path(&key), Path::new(path));
+        assert_eq!(display_key(&key), "/tmp/a\tb\nc");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = OsStr::from_bytes(b"/tmp/caf\xe9/\xff\xfe");
+        let key = path_to_key(path);
+        assert!(key.starts_with(BASE64_TAG));
+        assert_eq!(key_path(&key).as_os_str(), path);
+        assert_eq!(display_key(&key), "/tmp/caf\u{fffd}/\u{fffd}\u{fffd}");
+    }
+}
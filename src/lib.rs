@@ -0,0 +1,47 @@
+//! Directory scoped todo lists.
+//!
+//! Every directory gets its own todo list, kept in a store directory:
+//! `dirmap.tsv` maps a directory to the file holding its todos, one
+//! `id\ttext\tstatus` record per line after a header of `#` meta lines.
+//! With the `sqlite` feature the lists can live in an embedded SQLite
+//! database instead, see [`Storage`]. [`TodoStore`] is the API over it, the
+//! `todo` binary is a thin command line layer on top.
+mod alias;
+mod archive;
+mod config;
+mod date;
+mod dir_map;
+mod error;
+mod files;
+mod fsck;
+mod git;
+mod journal;
+mod key;
+mod location;
+mod record;
+mod shared;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod storage;
+mod store;
+mod tag;
+#[cfg(test)]
+mod temp_dir;
+mod tsv;
+
+pub use archive::Archived;
+pub use config::Config;
+pub use date::Date;
+pub use error::{Error, Result};
+pub use files::unix_now;
+pub use fsck::{Finding, Problem, Repair};
+pub use git::{Scope, current_branch, worktree_root};
+pub use journal::{JournalEntry, Operation};
+pub use key::{dir_key, display_key, key_path};
+pub use location::{StoreLocation, TODO_HOME_ENV};
+pub use record::{NewTodo, Priority, Record, Status, TodoList, TodoRef};
+pub use storage::{Backend, Storage};
+pub use store::{Access, DEFAULT_LOCK_TIMEOUT, Migration, StaleList, StaleReason, TodoStore};
+pub use tag::{parse_tag, tags_in};
+
+const COL_SEP_CH: char = '\t';
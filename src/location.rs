@@ -0,0 +1,172 @@
+use std::{
+    env::{home_dir, var_os},
+    ffi::OsString,
+    fs::{copy, create_dir_all, read_dir, remove_dir_all, rename},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{Access, Backend, Error, Result, TodoStore, store::backend_exists};
+
+/// Overrides where the store is
+pub const TODO_HOME_ENV: &str = "TODO_HOME";
+const XDG_DATA_HOME_ENV: &str = "XDG_DATA_HOME";
+/// Store directory under the XDG data home
+const STORE_DIR_NAME: &str = "dir-todo";
+/// Store directory under the home directory used before XDG support
+const LEGACY_DIR_NAME: &str = "todo";
+
+/// Where the store is and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreLocation {
+    /// Set by `--store` or `$TODO_HOME`
+    Explicit(PathBuf),
+    /// `$XDG_DATA_HOME/dir-todo`, or `~/.local/share/dir-todo`
+    Xdg(PathBuf),
+    /// An existing `~/todo` store, used until it is moved to `xdg`
+    Legacy { path: PathBuf, xdg: PathBuf },
+}
+
+impl StoreLocation {
+    /// Finds the store: `$TODO_HOME`, then the XDG data directory, falling
+    /// back to the legacy `~/todo` while only that holds a store.
+    pub fn find() -> Result<Self> {
+        Self::resolve(var_os(TODO_HOME_ENV), home_dir(), var_os(XDG_DATA_HOME_ENV))
+    }
+
+    /// `find` given the values of `$TODO_HOME`, the home directory and
+    /// `$XDG_DATA_HOME`
+    fn resolve(
+        todo_home: Option<OsString>,
+        home: Option<PathBuf>,
+        data_home: Option<OsString>,
+    ) -> Result<Self> {
+        if let Some(todo_home) = todo_home.filter(|v| !v.is_empty()) {
+            return Ok(StoreLocation::Explicit(PathBuf::from(todo_home)));
+        }
+        let home = home.ok_or(Error::NoHomeDir)?;
+        let mut xdg = data_home
+            .map(PathBuf::from)
+            // the spec says relative values are invalid and to be ignored
+            .filter(|data_home| data_home.is_absolute())
+            .unwrap_or_else(|| home.join(".local").join("share"));
+        xdg.push(STORE_DIR_NAME);
+
+        let mut legacy = home;
+        legacy.push(LEGACY_DIR_NAME);
+        if !xdg.exists() && is_store(&mut legacy) {
+            return Ok(StoreLocation::Legacy { path: legacy, xdg });
+        }
+        Ok(StoreLocation::Xdg(xdg))
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            StoreLocation::Explicit(path) | StoreLocation::Xdg(path) => path,
+            StoreLocation::Legacy { path, .. } => path,
+        }
+    }
+
+    /// Moves a legacy store to its XDG location, returning the new location.
+    /// Other locations are returned as they are.
+    pub fn migrate(self, lock_timeout: Duration) -> Result<Self> {
+        let StoreLocation::Legacy { path, xdg } = self else {
+            return Ok(self);
+        };
+        // hold the old store's lock so nobody writes to it mid-move
+        let store = TodoStore::open(&path, None, Access::Write, lock_timeout)?;
+        if let Some(parent) = xdg.parent() {
+            create_dir_all(parent).map_err(Error::io(parent))?;
+        }
+        match rename(&path, &xdg) {
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                copy_dir(&path, &xdg)?;
+                remove_dir_all(&path).map_err(Error::io(&path))?;
+            }
+            other => other.map_err(Error::io(&path))?,
+        }
+        drop(store);
+        Ok(StoreLocation::Xdg(xdg))
+    }
+}
+
+/// Whether `dir` holds data of any backend
+fn is_store(dir: &mut PathBuf) -> bool {
+    backend_exists(dir, Backend::Tsv) || backend_exists(dir, Backend::Sqlite)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    create_dir_all(to).map_err(Error::io(to))?;
+    for entry in read_dir(from).map_err(Error::io(from))? {
+        let entry = entry.map_err(Error::io(from))?;
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            copy(entry.path(), &target).map_err(Error::io(entry.path()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NewTodo, temp_dir::TempDir};
+
+    #[test]
+    fn todo_home_comes_first() {
+        let found = StoreLocation::resolve(Some("/store".into()), None, Some("/data".into()));
+        assert_eq!(found.unwrap(), StoreLocation::Explicit("/store".into()));
+        let found = StoreLocation::resolve(Some("".into()), Some("/home/u".into()), None);
+        assert_eq!(
+            found.unwrap().path(),
+            Path::new("/home/u/.local/share/dir-todo")
+        );
+        assert!(matches!(
+            StoreLocation::resolve(None, None, None),
+            Err(Error::NoHomeDir)
+        ));
+    }
+
+    #[test]
+    fn relative_data_homes_are_ignored() {
+        let home = Some(PathBuf::from("/home/u"));
+        let found = StoreLocation::resolve(None, home.clone(), Some("/data".into()));
+        assert_eq!(found.unwrap(), StoreLocation::Xdg("/data/dir-todo".into()));
+        let found = StoreLocation::resolve(None, home, Some("data".into()));
+        assert_eq!(
+            found.unwrap().path(),
+            Path::new("/home/u/.local/share/dir-todo")
+        );
+    }
+
+    #[test]
+    fn legacy_stores_move_to_the_data_home() {
+        let temp = TempDir::new("location-legacy");
+        let home = temp.path().to_path_buf();
+        let data_home = Some(home.join("data").into_os_string());
+        let legacy = home.join(LEGACY_DIR_NAME);
+        // an empty ~/todo is not a store
+        create_dir_all(&legacy).unwrap();
+        let found = StoreLocation::resolve(None, Some(home.clone()), data_home.clone()).unwrap();
+        assert!(matches!(found, StoreLocation::Xdg(_)));
+
+        let mut store = TodoStore::open(&legacy, None, Access::Write, Duration::ZERO).unwrap();
+        store.add("/p", NewTodo::new("moved")).unwrap();
+        drop(store);
+        let found = StoreLocation::resolve(None, Some(home.clone()), data_home.clone()).unwrap();
+        assert!(matches!(found, StoreLocation::Legacy { .. }));
+        assert_eq!(found.path(), legacy);
+
+        let moved = found.migrate(Duration::ZERO).unwrap();
+        let xdg = home.join("data").join(STORE_DIR_NAME);
+        assert_eq!(moved, StoreLocation::Xdg(xdg.clone()));
+        assert!(!legacy.exists());
+        let mut store = TodoStore::open(&xdg, None, Access::Read, Duration::ZERO).unwrap();
+        assert_eq!(store.list("/p").unwrap().unwrap()[0].text, "moved");
+        let found = StoreLocation::resolve(None, Some(home), data_home).unwrap();
+        assert_eq!(found, StoreLocation::Xdg(xdg));
+    }
+}
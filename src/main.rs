@@ -1,98 +1,323 @@
-/// dir map is "path" => "hash of path"
 use std::{
-    env::{current_dir, home_dir},
-    fs::{OpenOptions, create_dir, read_to_string, rename},
-    hash::{DefaultHasher, Hash, Hasher},
-    io::{Read, Seek, SeekFrom, Write},
+    env::{current_dir, var},
+    fs::rename,
     path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    time::Duration,
 };
 
 use argh::FromArgs;
+use dir_todo::{
+    Access, Backend, Config, Date, Error, Finding, JournalEntry, Migration, Operation, Priority,
+    Problem, Record, Repair, Result, Scope, StaleReason, Status, StoreLocation, TodoRef, TodoStore,
+    current_branch, dir_key, display_key, parse_tag, tags_in, unix_now,
+};
 use inquire::Confirm;
-use tinyvec::TinyVec;
 
-const TODO_DIR_NAME: &str = "todo";
-const DIR_MAP_NAME: &str = "dirmap.tsv";
-const DIR_MAP_NEW_NAME: &str = "dirmap.new.tsv";
-const COL_SEP_CH: char = '\t';
-const ACTIVE_TODO: &str = "[ ]";
-const DONE_TODO: &str = "[✓]";
+const LOCK_TIMEOUT_ENV: &str = "TODO_LOCK_TIMEOUT";
+const BACKEND_ENV: &str = "TODO_BACKEND";
 
-fn main() {
+fn main() -> ExitCode {
     let todo: Todo = argh::from_env();
-    let mut todo_dir = {
-        let mut todo_dir = home_dir().expect("get home dir");
-        todo_dir.as_mut_os_string().reserve(256);
-        todo_dir.push(TODO_DIR_NAME);
-        todo_dir
-    };
-
-    let pwd = current_dir()
-        .map(|pwd| {
-            pwd.to_str()
-                .expect("pwd is utf-8 string")
-                .to_string()
-                .into_boxed_str()
-        })
-        .expect("get pwd");
-
-    //create todo dir in home
-    if !todo_dir.exists() {
-        create_dir(&todo_dir).expect("create todo");
-    }
-
-    let mut dir_map_handle = with_pushed(&mut todo_dir, DIR_MAP_NAME, |path| {
-        OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(true)
-            .open(path)
-    })
-    .expect("open dir map");
-
-    let mut dir_map_buf = String::with_capacity(
-        dir_map_handle
-            .metadata()
-            .map(|m| m.len() as usize)
-            .unwrap_or(4096),
-    );
-
-    dir_map_handle
-        .read_to_string(&mut dir_map_buf)
-        .expect("load all of dir map");
+    match run(todo) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    drop(dir_map_handle);
+fn run(todo: Todo) -> Result<()> {
+    let pwd_path = current_dir().map_err(Error::io("."))?;
 
     let cmd = todo.cmd.unwrap_or_default();
+    let lock_timeout = todo
+        .lock_timeout
+        .or_else(|| var(LOCK_TIMEOUT_ENV).ok().and_then(|t| t.parse().ok()))
+        .map(Duration::from_secs)
+        .unwrap_or(dir_todo::DEFAULT_LOCK_TIMEOUT);
+    let backend = match todo.backend {
+        Some(backend) => Some(backend),
+        None => var(BACKEND_ENV)
+            .ok()
+            .map(|b| b.parse::<Backend>())
+            .transpose()
+            .map_err(Error::Conflict)?,
+    };
+    let mut location = match todo.store {
+        Some(store) => StoreLocation::Explicit(store),
+        None => StoreLocation::find()?,
+    };
+    let mut moved_store = false;
+    if let Command::Migrate(_) = cmd {
+        if let StoreLocation::Legacy { path, xdg } = &location {
+            println!("moving the todo store from {path:?} to {xdg:?}");
+            moved_store = true;
+        }
+        location = location.migrate(lock_timeout)?;
+    } else if let StoreLocation::Legacy { path, xdg } = &location {
+        eprintln!("using the old todo store in {path:?}, `todo migrate` moves it to {xdg:?}");
+    }
+    if let Command::Fsck(check) = cmd {
+        return fsck(location.path(), backend, lock_timeout, check.repair);
+    }
+    let access = if cmd.is_read_only() || cmd.asks() {
+        Access::Read
+    } else {
+        Access::Write
+    };
+    let open_store = |access| TodoStore::open(location.path(), backend, access, lock_timeout);
+    // held until the end of run so every read-modify-write of the store is
+    // covered, commands that ask first close it while asking
+    let mut store = open_store(access)?;
+    let scope = todo.scope.unwrap_or(store.config().scope);
+    let pwd = dir_key(scope.apply(&pwd_path))?.into_boxed_str();
+
     match cmd {
         Command::New(new_todo) => {
-            create_new_todo(new_todo, &pwd, &mut todo_dir, &mut dir_map_buf);
+            let record = store.add(
+                &pwd,
+                dir_todo::NewTodo {
+                    branch: current_branch(&pwd_path),
+                    priority: new_todo.priority,
+                    due: new_todo.due.map(|due| due.0),
+                    tags: new_todo.tag.into_iter().map(|tag| tag.0).collect(),
+                    ..dir_todo::NewTodo::new(new_todo.text)
+                },
+            )?;
+            println!("added todo: \"{}\" at ID: {}", record.text, record.id);
         }
-        Command::List(all) => {
-            if all.all {
-                list_todos_all(dir_map_buf.as_str(), &mut todo_dir);
+        Command::List(list) => {
+            let up = (list.up || store.config().list_up) && !list.no_up;
+            let tags = list.tag.into_iter().map(|tag| tag.0).collect::<Vec<_>>();
+            let show = Show {
+                branch: list.branch,
+                today: Date::today(),
+                tags: &tags,
+                statuses: &list.status,
+                when: list.when,
+                since: list.since.map(|since| since.0),
+                before: list.before.map(|before| before.0),
+                age: list.age.then(unix_now),
+            };
+            if list.all && list.by_priority {
+                list_todos_by_priority(&mut store, show)?;
+            } else if list.all {
+                list_todos_all(&mut store, show)?;
+            } else if up || list.down {
+                list_todos_tree(&mut store, &pwd, up, list.down, show)?;
             } else {
-                list_todos_pwd(dir_map_buf.as_str(), &pwd, &mut todo_dir);
+                list_todos_pwd(&mut store, &pwd, show)?;
             }
         }
         Command::Update(update) => {
-            update_todo(update, &mut dir_map_buf, &pwd, &mut todo_dir);
+            let record = store.update(&pwd, update.todo, &update.new_text)?;
+            println!("updated todo: \"{}\" @ ID: {}", record.text, record.id);
         }
         Command::Delete(delete_todo_id) => {
-            delete_todo(delete_todo_id, dir_map_buf.as_str(), &pwd, &mut todo_dir);
+            let record = store.get(&pwd, delete_todo_id.todo)?;
+            drop(store);
+            if record.status.is_open() && !prompt_delete_open(&record) {
+                return Err(Error::Cancelled);
+            }
+            let mut store = open_store(Access::Write)?;
+            let todo = TodoRef::Id(record.id);
+            if store.get(&pwd, todo)? != record {
+                return Err(Error::Conflict(format!(
+                    "the todo with ID {} changed while its deletion was being confirmed",
+                    record.id
+                )));
+            }
+            let record = store.delete(&pwd, todo, !delete_todo_id.purge, |_record| true)?;
+            println!("deleting \"{}\" at ID: {}...", record.text, record.id);
+            if !delete_todo_id.purge {
+                println!("`todo restore {}` brings it back", record.id);
+            }
         }
-        Command::Done(done) => {
-            mark_done(done, dir_map_buf.as_str(), &pwd, &mut todo_dir);
+        Command::Archive(archive) => match archive.cmd {
+            ArchiveCommand::List(list) => list_archive(&mut store, &pwd, list.all)?,
+        },
+        Command::Restore(restore) => {
+            let record = store.restore(&pwd, restore.id)?;
+            println!("restored \"{}\" at ID: {}", record.text, record.id);
+        }
+        Command::Priority(priority) => {
+            let record = store.set_priority(&pwd, priority.todo, priority.priority)?;
+            match record.priority {
+                Some(priority) => println!("\"{}\" now has priority {priority}", record.text),
+                None => println!("\"{}\" has no priority now", record.text),
+            }
         }
+        Command::Reschedule(reschedule) => {
+            let due = reschedule.due.map(|due| due.0);
+            let record = store.set_due(&pwd, reschedule.todo, due)?;
+            match record.due {
+                Some(due) => println!("\"{}\" is now due {due}", record.text),
+                None => println!("\"{}\" has no due date now", record.text),
+            }
+        }
+        Command::Due(due) => {
+            let show = Show {
+                branch: false,
+                today: Date::today(),
+                tags: &[],
+                statuses: &[],
+                when: Stamp::Created,
+                since: None,
+                before: None,
+                age: None,
+            };
+            let until = due.within.map_or(show.today.add_days(7), |within| within.0);
+            let todos = store.due_until(until)?;
+            if todos.is_empty() {
+                println!("nothing is due by {until}");
+            }
+            let mut print_buf = String::with_capacity(todos.len() * 96);
+            for (dir, record) in &todos {
+                let label = format!("\"{}\" {}", display_key(dir), record.id);
+                write_todo(&mut print_buf, &label, record, show);
+            }
+            print!("{print_buf}");
+        }
+        Command::Tags(_tags) => list_tags(&mut store)?,
+        Command::Status(status) => {
+            mark_status(&mut store, &pwd, status.todo, status.status, status.reason)?
+        }
+        Command::Done(done) => mark_status(&mut store, &pwd, done.todo, Status::Done, None)?,
         Command::Active(active) => {
-            mark_active(active, dir_map_buf.as_str(), &pwd, &mut todo_dir);
+            mark_status(&mut store, &pwd, active.todo, Status::Active, None)?
+        }
+        Command::Undo(undo) => {
+            for _ in 0..undo.count {
+                let entry = store.undo()?;
+                println!("undid {}", describe_change(&entry));
+            }
+        }
+        Command::Redo(redo) => {
+            for _ in 0..redo.count {
+                let entry = store.redo()?;
+                println!("redid {}", describe_change(&entry));
+            }
+        }
+        Command::History(history) => {
+            let entries = store.history()?;
+            let now = unix_now();
+            for entry in &entries[entries.len().saturating_sub(history.limit)..] {
+                println!(
+                    "{:>8}  {}",
+                    format_age(now.saturating_sub(entry.at)),
+                    describe_change(entry)
+                );
+            }
+        }
+        Command::Migrate(_migrate) => migrate(&mut store, moved_store)?,
+        Command::Fsck(_) => unreachable!("fsck runs without opening the store"),
+        Command::Gc(gc) => collect_garbage(store, gc, || open_store(Access::Write))?,
+        Command::Config(config) => match config.cmd {
+            ConfigCommand::List(_list) => {
+                for key in Config::KEYS {
+                    let value = store.config().get(key).unwrap_or_default();
+                    println!("{key} = {value}");
+                }
+            }
+            ConfigCommand::Get(get) => match store.config().get(&get.key) {
+                Some(value) => println!("{value}"),
+                None => {
+                    return Err(Error::InvalidConfig(format!(
+                        "unknown setting \"{}\"",
+                        get.key
+                    )));
+                }
+            },
+            ConfigCommand::Set(set) => {
+                store.set_config(&set.key, &set.value)?;
+                println!("{} = {}", set.key, set.value);
+            }
+        },
+        Command::Relocate(relocate) => {
+            let moves = store.relocate(&dir_key(&relocate.old)?, &dir_key(&relocate.new)?)?;
+            print_moves(&moves);
+        }
+        Command::MvDir(mv) => {
+            let from = dir_key(&mv.from)?;
+            if mv.to.exists() {
+                return Err(Error::Conflict(format!("{:?} already exists", mv.to)));
+            }
+            let to = dir_key(&mv.to)?;
+            // fail on a taken key before anything is moved
+            let has_todos = !store.relocation(&from, &to)?.is_empty();
+            rename(&mv.from, &mv.to).map_err(Error::io(&mv.from))?;
+            println!("moved {:?} to {:?}", mv.from, mv.to);
+            if has_todos {
+                print_moves(&store.relocate(&from, &to)?);
+            }
+        }
+        Command::Share(_share) => {
+            let (path, records) = store.share(&pwd)?;
+            println!("moved {records} todos to {path:?}");
         }
+        Command::Unshare(_unshare) => {
+            let records = store.unshare(&pwd)?;
+            println!("moved {records} todos back into the store");
+        }
+        Command::Alias(alias) => match alias.cmd {
+            AliasCommand::Add(add) => {
+                let alias = dir_key(&add.alias)?;
+                store.add_alias(&alias, &dir_key(&add.dir)?)?;
+                println!(
+                    "\"{}\" now uses the todos of \"{}\"",
+                    display_key(&alias),
+                    display_key(store.resolve(&alias))
+                );
+            }
+            AliasCommand::Remove(remove) => {
+                let alias = dir_key(&remove.alias)?;
+                let dir = store.remove_alias(&alias)?;
+                println!(
+                    "removed alias \"{}\" of \"{}\"",
+                    display_key(&alias),
+                    display_key(&dir)
+                );
+            }
+            AliasCommand::List(_list) => {
+                for (alias, dir) in store.aliases() {
+                    println!("\"{}\" -> \"{}\"", display_key(alias), display_key(dir));
+                }
+            }
+        },
+        Command::Storage(storage) => match storage.cmd {
+            StorageCommand::Convert(convert) => {
+                let from = store.backend();
+                let (lists, backup) = store.convert(convert.to)?;
+                println!("converted {lists} todo lists from {from} to {}", convert.to);
+                println!("the {from} data was moved to {backup:?}");
+            }
+        },
     }
+    Ok(())
 }
 
 #[derive(Debug, FromArgs, PartialEq)]
 /// Directory mapped TODO
 struct Todo {
+    #[argh(option)]
+    /// seconds to wait for another todo process to release the store
+    /// (default: $TODO_LOCK_TIMEOUT or 10)
+    lock_timeout: Option<u64>,
+    #[argh(option)]
+    /// directory of the todo store (default: $TODO_HOME, else
+    /// $XDG_DATA_HOME/dir-todo)
+    store: Option<PathBuf>,
+    #[argh(option)]
+    /// storage backend, tsv or sqlite
+    /// (default: $TODO_BACKEND or whichever the store has data for)
+    backend: Option<Backend>,
+    #[argh(option)]
+    /// whose todo list to use, dir for the working directory or git for the
+    /// root of its git worktree (default: config scope)
+    scope: Option<Scope>,
     #[argh(subcommand)]
     cmd: Option<Command>,
 }
@@ -104,8 +329,56 @@ enum Command {
     List(ListTodo),
     Update(UpdateTodo),
     Delete(DeleteTodoId),
+    Archive(ArchiveCmd),
+    Restore(Restore),
+    Priority(SetPriority),
+    Reschedule(Reschedule),
+    Due(DueTodos),
+    Tags(Tags),
+    Status(SetStatus),
     Done(Done),
     Active(Active),
+    Undo(Undo),
+    Redo(Redo),
+    History(History),
+    Migrate(Migrate),
+    Fsck(Fsck),
+    Gc(Gc),
+    Config(ConfigCmd),
+    Relocate(Relocate),
+    MvDir(MvDir),
+    Share(Share),
+    Unshare(Unshare),
+    Alias(AliasCmd),
+    Storage(StorageCmd),
+}
+
+impl Command {
+    /// Commands that only read the store can share the lock
+    fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Self::List(_)
+                | Self::Due(_)
+                | Self::Tags(_)
+                | Self::History(_)
+                | Self::Archive(ArchiveCmd {
+                    cmd: ArchiveCommand::List(_)
+                })
+                | Self::Alias(AliasCmd {
+                    cmd: AliasCommand::List(_)
+                })
+                | Self::Config(ConfigCmd {
+                    cmd: ConfigCommand::List(_) | ConfigCommand::Get(_)
+                })
+        )
+    }
+
+    /// Commands that ask for confirmation, which can take the user a while,
+    /// so they read the store, close it to ask and then reopen it to write
+    fn asks(&self) -> bool {
+        matches!(self, Self::Delete(_) | Self::Gc(_))
+    }
 }
 
 impl Default for Command {
@@ -120,29 +393,110 @@ impl Default for Command {
 struct NewTodo {
     #[argh(positional)]
     text: String,
+    #[argh(option, short = 'p')]
+    /// priority from A, the most urgent, to Z
+    priority: Option<Priority>,
+    #[argh(option)]
+    /// due date: 2026-11-01, today, tomorrow, a weekday or 3d, 2w, 1m from
+    /// today
+    due: Option<DueDate>,
+    #[argh(option)]
+    /// add a tag, +tag or @context; the +tag and @context words of the text
+    /// are tags too
+    tag: Vec<TagArg>,
 }
 
-impl NewTodo {
-    // fn active_record_buff_size(&self) -> usize {
-    //     self.text.len() + 2 + 1 + ACTIVE_TODO.len() + 2
-    // }
+/// A `+tag` or `@context` argument, a bare word being a `+tag`
+#[derive(Debug, Clone, PartialEq)]
+struct TagArg(String);
 
-    fn io_write_as_active(&self, buf: &mut impl std::io::Write, id: u64) -> std::io::Result<()> {
-        writeln!(
-            buf,
-            "{id}{COL_SEP_CH}{}{COL_SEP_CH}{ACTIVE_TODO}",
-            &self.text
-        )
+impl FromStr for TagArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        parse_tag(s)
+            .map(TagArg)
+            .ok_or_else(|| format!("\"{s}\" is not a tag like bug, +bug or @home"))
     }
 }
 
+/// A date argument, which can be relative to today
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DueDate(Date);
+
+impl FromStr for DueDate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Date::parse(s, Date::today())
+            .map(DueDate)
+            .ok_or_else(|| format!("\"{s}\" is not a date like 2026-11-01, friday or 3d"))
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Change the due date of a todo.
+#[argh(subcommand, name = "reschedule")]
+struct Reschedule {
+    #[argh(positional)]
+    /// ID of the todo, or %N for the Nth todo listed
+    todo: TodoRef,
+    #[argh(positional)]
+    /// new due date, left out to clear it
+    due: Option<DueDate>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List the todos of every directory that are due soon or overdue.
+#[argh(subcommand, name = "due")]
+struct DueTodos {
+    #[argh(option)]
+    /// list what is due up to this date or this far ahead, like 3d
+    /// (default: 7d)
+    within: Option<DueDate>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Count the todos of each tag in every directory.
+#[argh(subcommand, name = "tags")]
+struct Tags {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Change the priority of a todo.
+#[argh(subcommand, name = "priority")]
+struct SetPriority {
+    #[argh(positional)]
+    /// ID of the todo, or %N for the Nth todo listed
+    todo: TodoRef,
+    #[argh(positional)]
+    /// priority from A, the most urgent, to Z, left out to clear it
+    priority: Option<Priority>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Change the status of a todo: active, in-progress, blocked, waiting, done
+/// or cancelled.
+#[argh(subcommand, name = "status")]
+struct SetStatus {
+    #[argh(positional)]
+    /// ID of the todo, or %N for the Nth todo listed
+    todo: TodoRef,
+    #[argh(positional)]
+    /// the new status
+    status: Status,
+    #[argh(positional)]
+    /// why a blocked or waiting todo is held up
+    reason: Option<String>,
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 /// Mark a todo done.
 #[argh(subcommand, name = "done")]
 struct Done {
     #[argh(positional)]
-    /// index of the todo (in this directory) to mark as done
-    index: u64,
+    /// ID of the todo (in this directory) to mark as done, or %N for the Nth
+    /// todo listed
+    todo: TodoRef,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -150,8 +504,9 @@ struct Done {
 #[argh(subcommand, name = "active")]
 struct Active {
     #[argh(positional)]
-    /// index of the todo (in this directory) to mark as active
-    index: u64,
+    /// ID of the todo (in this directory) to mark as active, or %N for the
+    /// Nth todo listed
+    todo: TodoRef,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -162,6 +517,131 @@ struct ListTodo {
     #[argh(switch, short = 'a')]
     /// list all Todos regardless of directory
     all: bool,
+    #[argh(switch)]
+    /// also list the todos of parent directories (default: config list.up)
+    up: bool,
+    #[argh(switch)]
+    /// don't list the todos of parent directories, despite list.up
+    no_up: bool,
+    #[argh(switch)]
+    /// also list the todos of every directory below this one
+    down: bool,
+    #[argh(switch)]
+    /// show the git branch each todo was created on
+    branch: bool,
+    #[argh(switch)]
+    /// with -a, list the todos of every directory together by priority
+    by_priority: bool,
+    #[argh(option)]
+    /// only list todos with this tag, +tag or @context; can be repeated
+    tag: Vec<TagArg>,
+    #[argh(option)]
+    /// only list todos with this status, like blocked; can be repeated
+    status: Vec<Status>,
+    #[argh(option, default = "Stamp::Created")]
+    /// what --since and --before look at: created, updated or completed
+    /// (default: created)
+    when: Stamp,
+    #[argh(option)]
+    /// only list todos created (or see --when) on or after this date, like
+    /// 2026-10-01, monday or 7d ago
+    since: Option<PastDate>,
+    #[argh(option)]
+    /// only list todos created (or see --when) before this date
+    before: Option<PastDate>,
+    #[argh(switch)]
+    /// show how long ago each todo was created and finished
+    age: bool,
+}
+
+/// Which timestamp of todos `list --since` and `--before` filter on
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Stamp {
+    #[default]
+    Created,
+    Updated,
+    Completed,
+}
+
+impl Stamp {
+    fn of(self, record: &Record) -> Option<u64> {
+        match self {
+            Stamp::Created => record.created_at,
+            Stamp::Updated => record.updated_at,
+            Stamp::Completed => record.completed_at,
+        }
+    }
+}
+
+impl FromStr for Stamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "created" => Ok(Stamp::Created),
+            "updated" => Ok(Stamp::Updated),
+            "completed" => Ok(Stamp::Completed),
+            _ => Err(format!("\"{s}\" is not created, updated or completed")),
+        }
+    }
+}
+
+/// A date argument looking back from today, where 3d is three days ago
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PastDate(Date);
+
+impl FromStr for PastDate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Date::parse_past(s, Date::today())
+            .map(PastDate)
+            .ok_or_else(|| format!("\"{s}\" is not a date like 2026-10-01, monday or 7d"))
+    }
+}
+
+/// What listings show besides the status and text of todos
+#[derive(Debug, Clone, Copy)]
+struct Show<'a> {
+    branch: bool,
+    /// Due dates are shown relative to it
+    today: Date,
+    /// Only todos with all of these tags are listed
+    tags: &'a [String],
+    /// Only todos with one of these statuses are listed, all if it's empty
+    statuses: &'a [Status],
+    /// The timestamp `since` and `before` filter on
+    when: Stamp,
+    since: Option<Date>,
+    before: Option<Date>,
+    /// The current time in seconds since the Unix epoch, to show the age of
+    /// todos
+    age: Option<u64>,
+}
+
+impl Show<'_> {
+    fn lists(&self, record: &Record) -> bool {
+        self.tags.iter().all(|tag| record.tags.contains(tag))
+            && (self.statuses.is_empty() || self.statuses.contains(&record.status))
+            && self.in_period(record)
+    }
+
+    /// Whether the `when` timestamp of `record` is within `since` and
+    /// `before`; todos without it are only listed when neither is set
+    fn in_period(&self, record: &Record) -> bool {
+        if self.since.is_none() && self.before.is_none() {
+            return true;
+        }
+        let Some(day) = self.when.of(record).map(Date::from_unix) else {
+            return false;
+        };
+        self.since.is_none_or(|since| day >= since) && self.before.is_none_or(|before| day < before)
+    }
+
+    /// Whether any of `records` is listed, so the directory is worth a heading
+    fn lists_any(&self, records: &[Record]) -> bool {
+        records.iter().any(|record| self.lists(record))
+    }
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -169,69 +649,260 @@ struct ListTodo {
 #[argh(subcommand, name = "update")]
 struct UpdateTodo {
     #[argh(positional)]
-    /// todo ID number
-    id: u64,
+    /// todo ID number, or %N for the Nth todo listed
+    todo: TodoRef,
     #[argh(positional)]
     /// new text of todo
     new_text: String,
 }
 
+#[derive(FromArgs, PartialEq, Debug)]
+/// Revert the latest changes to todos, in any directory.
+#[argh(subcommand, name = "undo")]
+struct Undo {
+    #[argh(positional, default = "1")]
+    /// how many changes to revert
+    count: usize,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Apply undone changes again.
+#[argh(subcommand, name = "redo")]
+struct Redo {
+    #[argh(positional, default = "1")]
+    /// how many changes to apply again
+    count: usize,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Show the latest changes to todos, in every directory.
+#[argh(subcommand, name = "history")]
+struct History {
+    #[argh(option, short = 'n', default = "20")]
+    /// how many changes to show
+    limit: usize,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Upgrade the todo store written by an older version.
+#[argh(subcommand, name = "migrate")]
+struct Migrate {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Check the todo store for damage.
+#[argh(subcommand, name = "fsck")]
+struct Fsck {
+    #[argh(switch)]
+    /// fix what can be fixed without losing todos and move the rest into the
+    /// store's quarantine directory
+    repair: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Drop the todo lists of directories that are gone or have nothing left to
+/// do.
+#[argh(subcommand, name = "gc")]
+struct Gc {
+    #[argh(switch, short = 'y')]
+    /// don't ask for confirmation
+    yes: bool,
+    #[argh(switch)]
+    /// delete the todos instead of moving them to the archive
+    remove: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Show or change the settings of the todo store.
+#[argh(subcommand, name = "config")]
+struct ConfigCmd {
+    #[argh(subcommand)]
+    cmd: ConfigCommand,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum ConfigCommand {
+    List(ListConfig),
+    Get(GetConfig),
+    Set(SetConfig),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Show every setting.
+#[argh(subcommand, name = "list")]
+struct ListConfig {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Show a setting.
+#[argh(subcommand, name = "get")]
+struct GetConfig {
+    #[argh(positional)]
+    key: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Change a setting.
+#[argh(subcommand, name = "set")]
+struct SetConfig {
+    #[argh(positional)]
+    /// list.up or scope
+    key: String,
+    #[argh(positional)]
+    value: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Move the todos of a directory that was moved or renamed to its new path,
+/// along with those of its subdirectories.
+#[argh(subcommand, name = "relocate")]
+struct Relocate {
+    #[argh(positional)]
+    /// where the directory was
+    old: PathBuf,
+    #[argh(positional)]
+    /// where it is now
+    new: PathBuf,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Move or rename a directory and its todos.
+#[argh(subcommand, name = "mv-dir")]
+struct MvDir {
+    #[argh(positional)]
+    from: PathBuf,
+    #[argh(positional)]
+    /// new path, which must not exist yet
+    to: PathBuf,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Keep the todos of this directory in a .todo.tsv file in it, to share them
+/// through version control.
+#[argh(subcommand, name = "share")]
+struct Share {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Move the todos of this directory's .todo.tsv file back into the store.
+#[argh(subcommand, name = "unshare")]
+struct Unshare {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Let other spellings of a directory use its todo list.
+#[argh(subcommand, name = "alias")]
+struct AliasCmd {
+    #[argh(subcommand)]
+    cmd: AliasCommand,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum AliasCommand {
+    Add(AddAlias),
+    Remove(RemoveAlias),
+    List(ListAliases),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Make a directory use the todo list of another.
+#[argh(subcommand, name = "add")]
+struct AddAlias {
+    #[argh(positional)]
+    /// the other spelling, e.g. a bind mount of the directory
+    alias: PathBuf,
+    #[argh(positional)]
+    /// directory whose todo list it uses
+    dir: PathBuf,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Remove an alias.
+#[argh(subcommand, name = "rm")]
+struct RemoveAlias {
+    #[argh(positional)]
+    alias: PathBuf,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List aliases.
+#[argh(subcommand, name = "list")]
+struct ListAliases {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Manage the storage backend of the todo store.
+#[argh(subcommand, name = "storage")]
+struct StorageCmd {
+    #[argh(subcommand)]
+    cmd: StorageCommand,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum StorageCommand {
+    Convert(Convert),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Move every todo list to another backend.
+#[argh(subcommand, name = "convert")]
+struct Convert {
+    #[argh(positional)]
+    /// backend to convert to, tsv or sqlite
+    to: Backend,
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 /// Delete a todo.
 #[argh(subcommand, name = "delete")]
 struct DeleteTodoId {
     #[argh(positional)]
-    /// todo number
+    /// todo ID number, or %N for the Nth todo listed
+    todo: TodoRef,
+    #[argh(switch)]
+    /// erase the todo instead of moving it to the archive
+    purge: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Browse deleted todos.
+#[argh(subcommand, name = "archive")]
+struct ArchiveCmd {
+    #[argh(subcommand)]
+    cmd: ArchiveCommand,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum ArchiveCommand {
+    List(ListArchive),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List the archived todos of this directory.
+#[argh(subcommand, name = "list")]
+struct ListArchive {
+    #[argh(switch, short = 'a')]
+    /// list the archived todos of every directory
+    all: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Bring an archived todo back.
+#[argh(subcommand, name = "restore")]
+struct Restore {
+    #[argh(positional)]
+    /// ID the todo had in this directory
     id: u64,
 }
 
-fn calculate_hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
-}
-
-fn with_pushed<P, F, Out>(buf: &mut PathBuf, to_push: P, mut f: F) -> Out
-where
-    P: AsRef<Path>,
-    F: FnMut(&Path) -> Out,
-{
-    buf.push(to_push);
-    let out = f(buf.as_path());
-    buf.pop();
-    out
-}
-
-// fn with_pushed_and_ext<P, F, Out, E>(buf: &mut PathBuf, to_push: P, ext: E, mut f: F) -> Out
-// where
-//     P: AsRef<Path>,
-//     F: FnMut(&Path) -> Out,
-//     E: AsRef<OsStr>,
-// {
-//     buf.push(to_push);
-//     buf.set_extension(ext);
-//     let out = f(buf.as_path());
-//     buf.pop();
-//     out
-// }
-
-fn save_dir_map(todo_path: &mut PathBuf, dir_map_buf: &mut String) -> std::io::Result<()> {
-    with_pushed(todo_path, DIR_MAP_NEW_NAME, |path| {
-        let mut handle = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(true)
-            .open(path)?;
-        handle.write_all(dir_map_buf.as_bytes())
-    })?;
-    let old = with_pushed(todo_path, DIR_MAP_NAME, |path| Box::from(path));
-    with_pushed(todo_path, DIR_MAP_NEW_NAME, |path| rename(path, &old))
-}
-
-fn prompt_delete_active() -> bool {
-    let ans = Confirm::new("This todo is active. Are you sure you want to delete it?")
-        .with_default(false)
-        .prompt();
+fn prompt_delete_open(record: &Record) -> bool {
+    confirm(&format!(
+        "This todo is {}, not done. Are you sure you want to delete it?",
+        record.status.name()
+    ))
+}
+
+fn confirm(message: &str) -> bool {
+    let ans = Confirm::new(message).with_default(false).prompt();
 
     match ans {
         Ok(true) => true,
@@ -243,475 +914,446 @@ fn prompt_delete_active() -> bool {
     }
 }
 
-fn create_new_todo(new_todo: NewTodo, pwd: &str, todo_dir: &mut PathBuf, dir_map_buf: &mut String) {
-    // reject todo with newlines or tabs
-
-    let text = new_todo.text.as_str();
-    if reject_nl_and_tab(text) {
-        return;
-    }
-
-    let dir_map_entries: Vec<(&str, &str)> = dir_map_buf
-        .lines()
-        .map(|line| {
-            let mut cols = line.split(COL_SEP_CH);
-            (
-                // key (dir)
-                cols.next().expect("read key"),
-                // value (number)
-                cols.next().expect("read todo file name"),
-            )
-        })
-        .collect();
-    let pwd_todo_map_entry = dir_map_entries.iter().find(|(k, _v)| **k == *pwd);
-    let new_id = match pwd_todo_map_entry {
-        Some((_path, index)) => {
-            // dir_map_entries.push((Some(pwd), Some(path_hash)));
-            // create file
-            let (todo_file_existed, mut todo_file_handle) = with_pushed(todo_dir, index, |path| {
-                (
-                    path.is_file(),
-                    OpenOptions::new()
-                        .read(true)
-                        .create(true)
-                        .append(true)
-                        .open(path)
-                        .expect("open todo"),
-                )
-            });
-            let mut todo_buf = String::with_capacity(
-                todo_file_handle
-                    .metadata()
-                    .map(|m| m.len() as usize + 1024)
-                    .unwrap_or(4096),
+fn list_todos_pwd(store: &mut TodoStore, pwd: &str, show: Show) -> Result<()> {
+    match store.list(pwd)? {
+        Some(records) if !show.lists_any(&records) => {
+            println!(
+                "No matching Todos @ PWD: \"{}\"",
+                display_key(store.resolve(pwd))
             );
-            todo_file_handle
-                .read_to_string(&mut todo_buf)
-                .expect("read todo file");
-
-            let next_id: Option<u64> = if todo_file_existed {
-                let mut next_id = 0;
-                let mut same = None::<u64>;
-                for line in todo_buf.lines() {
-                    let mut columns = line.split(COL_SEP_CH);
-                    let old_id = columns
-                        .next()
-                        .and_then(|old_id_str| old_id_str.parse::<u64>().ok())
-                        .unwrap_or(0);
-                    if old_id >= next_id {
-                        next_id = old_id + 1
-                    }
-                    let old_text = columns.next();
-                    if let Some(old) = old_text {
-                        if same.is_none() && old == new_todo.text {
-                            same = Some(old_id)
-                        }
-                    }
-                }
-                match same {
-                    Some(old_id) => {
-                        println!(
-                            "the todo: \"{}\" already exists at id: {old_id}",
-                            new_todo.text
-                        );
-                        None
-                    }
-                    None => Some(next_id),
-                }
-            } else {
-                Some(0)
-            };
-
-            if let Some(id) = next_id {
-                new_todo
-                    .io_write_as_active(&mut todo_file_handle, id)
-                    .expect("write new todo to file");
-            }
-
-            next_id
         }
-        _ => {
-            let mut out_buf = String::with_capacity(20 + 4);
+        Some(records) => {
             use std::fmt::Write as _;
-            let path_hash = calculate_hash(&pwd);
-            write!(&mut out_buf, "{path_hash}.tsv").unwrap();
-            // create file
-            let mut todo_file_handle = with_pushed(todo_dir, &out_buf, |path| {
-                OpenOptions::new()
-                    .read(true)
-                    .create(true)
-                    .append(true)
-                    .open(path)
-            })
-            .expect("open todo");
-
-            new_todo
-                .io_write_as_active(&mut todo_file_handle, 0)
-                .expect("write new todo to file");
-
-            writeln!(dir_map_buf, "{pwd}\t{out_buf}").unwrap();
-            save_dir_map(todo_dir, dir_map_buf).expect("write new entry to dir map");
-            Some(0)
+            let mut print_buf = String::with_capacity(records.len() * 64 + 256);
+            writeln!(
+                &mut print_buf,
+                "\nTodo: \"{}\"",
+                display_key(store.resolve(pwd))
+            )
+            .unwrap();
+            write_todos(&mut print_buf, &records, show, true);
+            println!("{print_buf}");
+        }
+        None => {
+            println!("No Todos @ PWD: \"{}\"", display_key(pwd));
+            for stranded in store.stranded(pwd)? {
+                let stranded = display_key(&stranded);
+                println!(
+                    "\"{stranded}\" is gone, if it was moved here `todo relocate \"{stranded}\" .` brings its todos along"
+                );
+            }
         }
-    };
-    if let Some(new_id) = new_id {
-        println!("added todo: \"{}\" at ID: {new_id}", &new_todo.text);
     }
+    Ok(())
 }
 
-fn update_todo(update: UpdateTodo, dir_map_buf: &mut String, pwd: &str, todo_dir: &mut PathBuf) {
-    use std::fmt::Write;
-
-    let text = update.new_text.as_str();
-    if reject_nl_and_tab(text) {
-        return;
+fn list_todos_tree(
+    store: &mut TodoStore,
+    pwd: &str,
+    up: bool,
+    down: bool,
+    show: Show,
+) -> Result<()> {
+    let lists = store.list_tree(pwd, up, down)?;
+    if lists.is_empty() {
+        return list_todos_pwd(store, pwd, show);
     }
-
-    let pwd_todo_map_entry = dir_map_entries(dir_map_buf).find(|(k, _v)| **k == *pwd);
-    match pwd_todo_map_entry {
-        Some((_path, index)) => {
-            // file must already exist
-            let todo_file_handle = with_pushed(todo_dir, index, |path| {
-                if !path.is_file() {
-                    eprintln!("there are no todos to edit @: {path:?}");
-                    return None;
-                }
-                Some(
-                    OpenOptions::new()
-                        .read(true)
-                        .write(true)
-                        .create(true)
-                        .append(false)
-                        .truncate(false)
-                        .open(path)
-                        .expect("open todo"),
-                )
-            });
-            let mut todo_file_handle = match todo_file_handle {
-                Some(tfh) => tfh,
-                None => return,
-            };
-            let todo_file_len = todo_file_handle
-                .metadata()
-                .map(|m| m.len() as usize)
-                .unwrap_or(4096);
-            let mut todo_buf = String::with_capacity(todo_file_len);
-            todo_file_handle
-                .read_to_string(&mut todo_buf)
-                .expect("read todo file");
-
-            // is there a todo at that ID?
-            let existing_record = todo_buf.lines().enumerate().find_map(|(idx, line)| {
-                line.split_once(COL_SEP_CH).and_then(|(id_str, rest)| {
-                    rest.split_once(COL_SEP_CH).and_then(|(_old_txt, status)| {
-                        if id_str.parse::<u64>().ok().is_some_and(|id| id == update.id) {
-                            Some((idx, id_str, status))
-                        } else {
-                            None
-                        }
-                    })
-                })
-            });
-            let existing_record = match existing_record {
-                Some(er) => er,
-                None => {
-                    eprintln!("no record @ ID {} and path \"{}\"", update.id, pwd);
-                    return;
-                }
-            };
-
-            // resuse dir map buf for output of the todo file
-            dir_map_buf.clear();
-            if let Some(additional) =
-                (todo_file_len + update.new_text.len()).checked_sub(dir_map_buf.capacity())
-            {
-                dir_map_buf.reserve(additional);
-            }
-
-            todo_file_handle
-                .seek(SeekFrom::Start(0))
-                .expect("set write position to star of todo file");
-
-            // re-write todos with the update
-            for (og_idx, og_line) in todo_buf.lines().enumerate() {
-                if og_idx == existing_record.0 {
-                    writeln!(
-                        dir_map_buf,
-                        "{}{COL_SEP_CH}{}{COL_SEP_CH}{}",
-                        existing_record.1, update.new_text, existing_record.2
-                    )
-                    .unwrap()
-                } else {
-                    writeln!(dir_map_buf, "{og_line}").unwrap();
-                }
-            }
-            todo_file_handle
-                .set_len(dir_map_buf.len() as u64)
-                .expect("set length of todo file to output buffer");
-            todo_file_handle
-                .write_all(dir_map_buf.as_bytes())
-                .expect("write update to todo file");
-            todo_file_handle.flush().expect("flush todo file");
-            todo_file_handle.sync_all().expect("sync todo file");
-        }
-        _ => {
-            eprintln!("there are no todos to edit @: \"{pwd}\"");
+    use std::fmt::Write as _;
+    let pwd = store.resolve(pwd).to_string();
+    let mut print_buf = String::with_capacity(4096);
+    for (dir, records) in lists {
+        if !show.lists_any(&records) {
+            continue;
         }
-    };
-    println!("updated todo: \"{}\" @ ID: {}", &update.new_text, update.id);
+        writeln!(&mut print_buf, "\nTodo: \"{}\"", display_key(&dir)).unwrap();
+        // %N only means something in the working directory, the other lists
+        // are labelled by ID alone
+        write_todos(&mut print_buf, &records, show, dir == pwd);
+    }
+    println!("{print_buf}");
+    Ok(())
 }
 
-fn list_todos_pwd(dir_map_buf: &str, pwd: &str, todo_dir: &mut PathBuf) {
-    // find for pwd
-    let pwd_todo_path = dir_map_entries(dir_map_buf).find(|(k, _v)| **k == *pwd);
-    match pwd_todo_path {
-        Some((pwd_path, todo_file_path_str)) => {
-            use std::fmt::Write as _;
-            let todo_file_path: &Path = todo_file_path_str.as_ref();
-            // open the file
-            let mut print_buf = String::with_capacity(
-                todo_file_path
-                    .metadata()
-                    .map(|m| m.len() as usize + 256)
-                    .unwrap_or(4096),
-            );
-            let todo_raw = with_pushed(todo_dir, todo_file_path, |path| {
-                writeln!(&mut print_buf, "\nTodo: \"{pwd_path}\"").unwrap();
-                read_to_string(path).expect("load todo file")
-            });
-            write_todos_in_file(&mut print_buf, &todo_raw);
-            println!("{print_buf}")
+fn list_todos_all(store: &mut TodoStore, show: Show) -> Result<()> {
+    use std::fmt::Write as _;
+    let mut print_buf = String::with_capacity(10_240);
+    for (dir, records) in store.list_all()? {
+        if !show.lists_any(&records) {
+            continue;
         }
-        None => println!("No Todos @ PWD: \"{pwd}\""),
+        writeln!(&mut print_buf, "\nTodo: \"{}\"", display_key(&dir)).unwrap();
+        for (alias, _dir) in store.aliases().filter(|(_alias, to)| *to == dir) {
+            writeln!(&mut print_buf, "  alias: \"{}\"", display_key(alias)).unwrap();
+        }
+        write_todos(&mut print_buf, &records, show, true);
     }
+    println!("{print_buf}");
+    Ok(())
 }
 
-fn list_todos_all(dir_map: &str, todo_dir: &mut PathBuf) {
+fn list_archive(store: &mut TodoStore, pwd: &str, all: bool) -> Result<()> {
     use std::fmt::Write as _;
-    let dir_map_entries = dir_map_entries(dir_map);
-    let mut print_buf = String::with_capacity(10_240);
-    let mut in_buf = String::with_capacity(10_240);
-    for (dir, file_name) in dir_map_entries {
-        with_pushed(todo_dir, file_name, |path| {
-            writeln!(&mut print_buf, "\nTodo: \"{}\"", &dir).unwrap();
-            if let Ok(mut handle) = OpenOptions::new()
-                .read(true)
-                .write(false)
-                .create(false)
-                .open(path)
-                .inspect_err(|e| eprintln!("can't open {:?} due to {e}", &path))
-            {
-                handle
-                    .read_to_string(&mut in_buf)
-                    .inspect_err(|e| eprintln!("can't read {:?} due to {e}", &path))
-                    .ok();
-            }
-        });
-        write_todos_in_file(&mut print_buf, &in_buf);
-        in_buf.clear();
+    let pwd = store.resolve(pwd).to_string();
+    let now = unix_now();
+    let mut print_buf = String::with_capacity(4096);
+    let mut last_dir = None;
+    for entry in store.archived()? {
+        if !all && entry.dir != pwd {
+            continue;
+        }
+        if last_dir.as_ref() != Some(&entry.dir) {
+            writeln!(
+                &mut print_buf,
+                "\nArchived: \"{}\"",
+                display_key(&entry.dir)
+            )
+            .unwrap();
+        }
+        let first_line = entry.record.text.lines().next().unwrap_or_default();
+        writeln!(
+            &mut print_buf,
+            "{} - {}: {first_line} (archived {})",
+            entry.record.id,
+            entry.record.status,
+            format_age(now.saturating_sub(entry.archived_at))
+        )
+        .unwrap();
+        last_dir = Some(entry.dir);
     }
-    println!("{print_buf}")
+    if print_buf.is_empty() {
+        println!("No archived todos");
+    } else {
+        println!("{print_buf}");
+    }
+    Ok(())
 }
 
-fn mark_done(done: Done, dir_map: &str, pwd: &str, todo_dir: &mut PathBuf) {
-    mark_status(MarkStatus::Done(done), dir_map, pwd, todo_dir);
+fn list_todos_by_priority(store: &mut TodoStore, show: Show) -> Result<()> {
+    let mut todos = store
+        .list_all()?
+        .into_iter()
+        .flat_map(|(dir, records)| records.into_iter().map(move |record| (dir.clone(), record)))
+        .filter(|(_dir, record)| show.lists(record))
+        .collect::<Vec<_>>();
+    todos.sort_by_key(|(_dir, record)| (record.priority.is_none(), record.priority));
+    let mut print_buf = String::with_capacity(todos.len() * 96 + 64);
+    print_buf.push_str("\nTodo: every directory by priority\n");
+    for (dir, record) in &todos {
+        let label = format!("\"{}\" {}", display_key(dir), record.id);
+        write_todo(&mut print_buf, &label, record, show);
+    }
+    println!("{print_buf}");
+    Ok(())
 }
 
-fn mark_active(active: Active, dir_map: &str, pwd: &str, todo_dir: &mut PathBuf) {
-    mark_status(MarkStatus::Active(active), dir_map, pwd, todo_dir);
+fn list_tags(store: &mut TodoStore) -> Result<()> {
+    // tag -> (todos, open todos)
+    let mut counts = std::collections::HashMap::<String, (usize, usize)>::new();
+    for (_dir, records) in store.list_all()? {
+        for record in records {
+            for tag in record.tags {
+                let count = counts.entry(tag).or_default();
+                count.0 += 1;
+                count.1 += usize::from(record.status.is_open());
+            }
+        }
+    }
+    if counts.is_empty() {
+        println!("No tagged todos");
+        return Ok(());
+    }
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|(tag_a, a), (tag_b, b)| b.0.cmp(&a.0).then_with(|| tag_a.cmp(tag_b)));
+    let width = counts
+        .iter()
+        .map(|(tag, _)| tag.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (tag, (todos, open)) in counts {
+        println!("{tag:width$} {todos:>4} ({open} open)");
+    }
+    Ok(())
 }
 
-#[derive(Debug)]
-enum MarkStatus {
-    Done(Done),
-    Active(Active),
+fn mark_status(
+    store: &mut TodoStore,
+    pwd: &str,
+    todo: TodoRef,
+    status: Status,
+    reason: Option<String>,
+) -> Result<()> {
+    let record = store.set_status(pwd, todo, status, reason)?;
+    println!(
+        "Seting: \"{}\" @: \"{}\" to {}...",
+        record.text,
+        display_key(pwd),
+        status.name()
+    );
+    Ok(())
 }
 
-impl From<&MarkStatus> for u64 {
-    fn from(value: &MarkStatus) -> Self {
-        match value {
-            MarkStatus::Done(done) => done.index,
-            MarkStatus::Active(active) => active.index,
+fn migrate(store: &mut TodoStore, moved_store: bool) -> Result<()> {
+    let migrations = store.migrate()?;
+    if migrations.is_empty() && !moved_store {
+        println!("nothing to migrate");
+    }
+    for migration in migrations {
+        match migration {
+            Migration::Renamed { dir, from, to } => {
+                println!("migrated \"{}\": {from} -> {to}", display_key(&dir))
+            }
+            Migration::Conflict { dir, existing } => {
+                eprintln!(
+                    "can't migrate \"{}\": {existing:?} already exists",
+                    display_key(&dir)
+                )
+            }
+            Migration::BackedUp(backup) => println!("backed the store up to {backup:?}"),
+            Migration::Upgraded { file, from, to } => {
+                println!("upgraded {file:?} from format {from} to {to}")
+            }
+            Migration::Canonicalized { from, to } => {
+                println!(
+                    "moved the todos of \"{}\" to \"{}\"",
+                    display_key(&from),
+                    display_key(&to)
+                )
+            }
+            Migration::Duplicate { dir, canonical } => {
+                eprintln!(
+                    "can't migrate \"{}\": \"{}\" has a todo list of its own",
+                    display_key(&dir),
+                    display_key(&canonical)
+                )
+            }
         }
     }
+    Ok(())
 }
 
-fn mark_status(status: MarkStatus, dir_map: &str, pwd: &str, todo_dir: &mut PathBuf) {
-    use std::fmt::Write as _;
-    #[derive(Debug)]
-    enum Change<'line> {
-        None(&'line str),
-        Some((u64, &'line str)),
-    }
-    let id_want: u64 = (&status).into();
-    let pwd_todo_path = dir_map_entries(dir_map).find(|(k, _v)| **k == *pwd);
-    match pwd_todo_path {
-        Some((pwd_path, todo_file_path_str)) => {
-            let todo_file_path: &Path = todo_file_path_str.as_ref();
-            // open the file
-            let todo_raw = with_pushed(todo_dir, todo_file_path, |path| {
-                read_to_string(path).expect("load todo file")
-            });
-            let mut new_todo_raw = String::with_capacity(todo_raw.len());
-
-            let todo_records = todo_raw.lines().filter_map(|line| {
-                let mut columns = line.split(COL_SEP_CH);
-
-                columns.next().and_then(|id_str| {
-                    id_str.parse::<u64>().ok().and_then(|id| {
-                        if id == id_want {
-                            let text = columns.next();
-                            text.map(|t| Change::Some((id, t)))
-                        } else {
-                            Some(Change::None(line))
-                        }
-                    })
-                })
-            });
-            for record in todo_records {
-                match record {
-                    Change::None(line) => writeln!(&mut new_todo_raw, "{line}").unwrap(),
-                    Change::Some((id, text)) => match &status {
-                        MarkStatus::Done(_done) => {
-                            println!("Seting: \"{text}\" @: \"{pwd_path}\" to done...");
-                            writeln!(&mut new_todo_raw, "{id}\t{text}\t{DONE_TODO}").unwrap()
-                        }
-                        MarkStatus::Active(_active) => {
-                            println!("Seting: \"{text}\" @: \"{pwd_path}\" to active...");
-                            writeln!(&mut new_todo_raw, "{id}\t{text}\t{ACTIVE_TODO}").unwrap()
-                        }
-                    },
-                }
-            }
-            let mut todo_file_handle = with_pushed(todo_dir, todo_file_path_str, |path| {
-                OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .truncate(true)
-                    .create(true)
-                    .open(path)
-                    .expect("open todo file")
-            });
-            todo_file_handle
-                .write_all(new_todo_raw.as_bytes())
-                .expect("write todo file");
+/// One line on what `entry` did, for `todo history`, `undo` and `redo`
+fn describe_change(entry: &JournalEntry) -> String {
+    let summary = |record: &Record| {
+        let first_line = record.text.lines().next().unwrap_or_default();
+        match record.priority {
+            Some(priority) => format!("{} {}: {priority} {first_line}", record.id, record.status),
+            None => format!("{} {}: {first_line}", record.id, record.status),
         }
-        None => println!("No Todos @ PWD: \"{pwd}\""),
-    }
+    };
+    let change = match (&entry.before, &entry.after) {
+        (Some(before), Some(after)) if entry.op == Operation::Status => match &after.reason {
+            Some(reason) => format!("{} -> {} ({reason})", summary(before), after.status),
+            None => format!("{} -> {}", summary(before), after.status),
+        },
+        (Some(before), Some(after)) if entry.op == Operation::Due => match after.due {
+            Some(due) => format!("{} -> due {due}", summary(before)),
+            None => format!("{} -> no due date", summary(before)),
+        },
+        (Some(before), Some(after)) if entry.op == Operation::Priority => {
+            let priority = after.priority.map(|p| p.to_string());
+            let priority = priority.as_deref().unwrap_or("no priority");
+            format!("{} -> {priority}", summary(before))
+        }
+        (Some(before), Some(after)) => format!("{} -> {}", summary(before), summary(after)),
+        (Some(record), None) => format!("{} -> deleted", summary(record)),
+        (None, Some(record)) => format!("new {}", summary(record)),
+        (None, None) => "a purged todo".to_string(),
+    };
+    format!("{} @ \"{}\": {change}", entry.op, display_key(&entry.dir))
 }
 
-fn delete_todo(delete_todo_id: DeleteTodoId, dir_map: &str, pwd: &str, todo_dir: &mut PathBuf) {
-    use std::fmt::Write as _;
-    let id_to_delete = delete_todo_id.id;
-    let pwd_todo_path = dir_map_entries(dir_map).find(|(k, _v)| **k == *pwd);
-    match pwd_todo_path {
-        Some((_pwd_path, todo_file_path_str)) => {
-            let raw_old_todo = with_pushed(todo_dir, todo_file_path_str, |path| {
-                read_to_string(path)
-                    .expect("read todo file")
-                    .into_boxed_str()
-            });
-            let mut lines: TinyVec<[(u64, &str); 20]> = TinyVec::new();
-            let mut cancel = false;
-            for line in raw_old_todo.lines() {
-                if let Some((id, rest)) = line
-                    .split_once(COL_SEP_CH)
-                    .and_then(|(id_text, rest)| id_text.parse::<u64>().ok().map(|id| (id, rest)))
-                {
-                    if id != id_to_delete {
-                        lines.push((id, rest));
-                    } else {
-                        let (text, status) = rest.split_once(COL_SEP_CH).unwrap_or_default();
-                        if status == ACTIVE_TODO {
-                            if prompt_delete_active() {
-                                println!("deleting \"{text}\" at ID: {id}...");
-                            } else {
-                                println!("canceling...");
-                                cancel = true;
-                                break;
-                            }
-                        } else {
-                            println!("deleting \"{text}\" at ID: {id}...");
-                        }
-                    }
-                }
-            }
-            if !cancel {
-                let renumbered_lines = lines.iter_mut().scan(0u64, |new_id, (_old_id, rest)| {
-                    let out = (*new_id, rest);
-                    *new_id += 1u64;
-                    Some(out)
-                });
-
-                let mut out_buf = String::with_capacity(raw_old_todo.len());
-                for line in renumbered_lines {
-                    writeln!(&mut out_buf, "{}{COL_SEP_CH}{}", line.0, line.1).unwrap()
-                }
+/// `seconds` in the largest whole unit, like `3d ago`
+fn format_age(seconds: u64) -> String {
+    const UNITS: [(u64, &str); 4] = [(86_400, "d"), (3_600, "h"), (60, "m"), (1, "s")];
+    let (size, unit) = UNITS
+        .into_iter()
+        .find(|(size, _unit)| seconds >= *size)
+        .unwrap_or(UNITS[3]);
+    format!("{}{unit} ago", seconds / size)
+}
 
-                let mut todo_file_handle = with_pushed(todo_dir, todo_file_path_str, |path| {
-                    OpenOptions::new()
-                        .read(true)
-                        .write(true)
-                        .truncate(true)
-                        .create(false)
-                        .open(path)
-                        .expect("open todo file")
-                });
-                todo_file_handle
-                    .write_all(out_buf.as_bytes())
-                    .expect("write todo file");
+fn print_moves(moves: &[(String, String)]) {
+    for (from, to) in moves {
+        println!(
+            "moved the todos of \"{}\" to \"{}\"",
+            display_key(from),
+            display_key(to)
+        );
+    }
+}
+
+/// Finds the stale lists in `store` and closes it to ask about them, then
+/// drops them in the store `reopen` opens to write
+fn collect_garbage(
+    mut store: TodoStore,
+    gc: Gc,
+    reopen: impl FnOnce() -> Result<TodoStore>,
+) -> Result<()> {
+    let stale = store.stale_lists()?;
+    drop(store);
+    for list in &stale {
+        let reason = match list.reason {
+            StaleReason::DirGone => "the directory is gone".to_string(),
+            StaleReason::Empty => "no todos".to_string(),
+            StaleReason::AllDone => {
+                format!("all {} todos done or cancelled", list.records.len())
             }
+        };
+        println!("\"{}\": {reason}", display_key(&list.dir));
+    }
+    let action = if gc.remove { "Delete" } else { "Archive" };
+    if !stale.is_empty()
+        && !gc.yes
+        && !confirm(&format!("{action} these {} todo lists?", stale.len()))
+    {
+        return Err(Error::Cancelled);
+    }
+    let mut store = reopen()?;
+    let max_age_days = store.config().archive_max_age_days;
+    if max_age_days > 0 {
+        let expired = store.expire_archive(max_age_days * 86_400)?;
+        if !expired.is_empty() {
+            println!(
+                "dropped {} todos archived more than {max_age_days} days ago",
+                expired.len()
+            );
         }
-        None => println!("No Todos @ PWD: \"{pwd}\""),
     }
+    if stale.is_empty() {
+        println!("nothing to clean up");
+        return Ok(());
+    }
+    if store.stale_lists()? != stale {
+        return Err(Error::Conflict(
+            "the todo lists changed while this was being confirmed, run todo gc again".to_string(),
+        ));
+    }
+    for list in stale {
+        let records = store.remove_list(&list.dir, !gc.remove)?;
+        let done = if gc.remove { "deleted" } else { "archived" };
+        println!(
+            "{done} \"{}\" with {} todos",
+            display_key(&list.dir),
+            records.len()
+        );
+    }
+    Ok(())
 }
 
-fn write_todos_in_file(print_buf: &mut String, raw_todo_file: &str) {
-    use std::fmt::Write as _;
-    let todo_records = raw_todo_file.lines().filter_map(|line| {
-        let mut columns = line.split(COL_SEP_CH);
-        let id = columns.next();
-        let text = columns.next();
-        let done = columns.next();
-
-        done.and_then(|done| id.zip(text).map(|(id, text)| (id, text, done)))
-    });
-    for record in todo_records {
-        let id = record.0;
-        let text = record.1;
-        let done = record.2;
-        writeln!(print_buf, "{id} - {done}: {text}").unwrap()
-    }
-}
-
-fn dir_map_entries(dir_map_buf: &str) -> impl Iterator<Item = (&str, &str)> {
-    dir_map_buf.lines().map(|line| {
-        let mut cols = line.split(COL_SEP_CH);
-        (
-            // key (dir)
-            cols.next().expect("read key"),
-            // value (number)
-            cols.next().expect("read todo file name"),
-        )
-    })
+fn fsck(
+    store_dir: &Path,
+    backend: Option<Backend>,
+    lock_timeout: Duration,
+    repair: bool,
+) -> Result<()> {
+    let findings = TodoStore::fsck(store_dir, backend, lock_timeout, repair)?;
+    if findings.is_empty() {
+        println!("no problems found");
+    }
+    for Finding { problem, repair } in &findings {
+        let problem = match problem {
+            Problem::MalformedLine {
+                path,
+                line,
+                content,
+            } => format!("{path:?} line {line} is malformed: \"{content}\""),
+            Problem::DuplicateId { dir, id } => format!(
+                "\"{}\" has more than one todo with ID {id}",
+                display_key(dir)
+            ),
+            Problem::MissingFile { dir, path } => format!(
+                "\"{}\" points at the missing todo file {path:?}",
+                display_key(dir)
+            ),
+            Problem::OrphanFile(path) => format!("no directory uses the todo file {path:?}"),
+            Problem::DuplicateKey { dir, path } => format!(
+                "\"{}\" is in the dir map again, pointing at {path:?}",
+                display_key(dir)
+            ),
+            Problem::Database(message) => format!("database: {message}"),
+        };
+        match repair {
+            None => println!("{problem}"),
+            Some(Repair::Removed) => println!("{problem}: removed"),
+            Some(Repair::Renumbered(id)) => println!("{problem}: renumbered to ID {id}"),
+            Some(Repair::Quarantined(path)) => println!("{problem}: moved to {path:?}"),
+        }
+    }
+    match findings.iter().filter(|f| f.repair.is_none()).count() {
+        0 => Ok(()),
+        problems => Err(Error::Damaged { problems }),
+    }
 }
 
-/// Returns true if a newline or tab is found in text
-fn reject_nl_and_tab(text: &str) -> bool {
-    let tab = text.contains('\t');
-    let nl = text.contains('\n');
-
-    if tab {
-        eprintln!("todo contains tab character");
+fn write_todos(print_buf: &mut String, records: &[Record], show: Show, positions: bool) {
+    // positions count the filtered out todos too, so %N means the same todo
+    // as in the unfiltered listing
+    for (position, record) in records.iter().enumerate() {
+        if !show.lists(record) {
+            continue;
+        }
+        let label = if positions {
+            format!("%{} {}", position + 1, record.id)
+        } else {
+            record.id.to_string()
+        };
+        write_todo(print_buf, &label, record, show);
     }
+}
 
-    if nl {
-        eprintln!("todo contains newline character");
+/// Writes the listing line of `record`, starting with `label`
+fn write_todo(print_buf: &mut String, label: &str, record: &Record, show: Show) {
+    use std::fmt::Write as _;
+    let line_start = print_buf.len();
+    write!(print_buf, "{label} - {}: ", record.status).unwrap();
+    if let Some(priority) = record.priority {
+        write!(print_buf, "{priority} ").unwrap();
     }
-
-    if nl || tab {
-        eprintln!("can't create todo");
+    // continuation lines of multi-line text line up under the first
+    let indent = print_buf[line_start..].chars().count();
+    for (idx, line) in record.text.lines().enumerate() {
+        if idx > 0 {
+            write!(print_buf, "\n{:indent$}", "").unwrap();
+        }
+        print_buf.push_str(line);
     }
-
-    nl || tab
+    let text_tags = tags_in(&record.text).collect::<Vec<_>>();
+    for tag in record
+        .tags
+        .iter()
+        .filter(|tag| !text_tags.contains(&tag.as_str()))
+    {
+        write!(print_buf, " {tag}").unwrap();
+    }
+    if let Some(reason) = &record.reason {
+        write!(print_buf, " ({}: {reason})", record.status.name()).unwrap();
+    }
+    if let Some(due) = record.due {
+        let days = show.today.days_until(due);
+        match days {
+            _ if !record.status.is_open() => write!(print_buf, " (due {due})"),
+            ..0 => write!(print_buf, " (OVERDUE by {}d, due {due})", -days),
+            0 => write!(print_buf, " (due today)"),
+            1 => write!(print_buf, " (due tomorrow)"),
+            _ => write!(print_buf, " (due in {days}d, {due})"),
+        }
+        .unwrap();
+    }
+    if let Some(branch) = record.branch.as_deref().filter(|_| show.branch) {
+        write!(print_buf, " ({branch})").unwrap();
+    }
+    if let (Some(now), Some(created_at)) = (show.age, record.created_at) {
+        write!(
+            print_buf,
+            " (created {}",
+            format_age(now.saturating_sub(created_at))
+        )
+        .unwrap();
+        if let Some(completed_at) = record.completed_at {
+            let age = format_age(now.saturating_sub(completed_at));
+            write!(print_buf, ", {} {age}", record.status.name()).unwrap();
+        }
+        print_buf.push(')');
+    }
+    print_buf.push('\n');
 }
@@ -0,0 +1,887 @@
+use std::{fmt, path::Path, str::FromStr};
+
+use crate::{COL_SEP_CH, Date, Error, Result};
+
+const ACTIVE_TODO: &str = "[ ]";
+const IN_PROGRESS_TODO: &str = "[~]";
+const BLOCKED_TODO: &str = "[!]";
+const WAITING_TODO: &str = "[…]";
+const DONE_TODO: &str = "[✓]";
+const CANCELLED_TODO: &str = "[✗]";
+/// Starts the meta lines of a todo file, records start with their ID
+pub(crate) const META_PREFIX: char = '#';
+const NEXT_ID_META: &str = "next_id";
+pub(crate) const FORMAT_META: &str = "format";
+const COLUMNS_META: &str = "columns";
+/// Version of the todo file format written:
+///
+/// 1. no `#format` line, `id\ttext\tstatus` with the text as is, so it
+///    couldn't hold tabs or newlines
+/// 2. text escaped
+/// 3. a `#columns` line names the columns of the records
+/// 4. a `branch` column
+/// 5. a `priority` column
+/// 6. a `due` column
+/// 7. a `tags` column
+/// 8. in progress, blocked, waiting and cancelled statuses and a `reason`
+///    column
+/// 9. `created`, `updated` and `completed` columns
+pub(crate) const FORMAT_VERSION: u32 = 9;
+/// Separates the tags in the `tags` column, tags have no whitespace
+const TAG_SEP_CH: char = ' ';
+const ESCAPE_CH: char = '\\';
+/// The highest ID a todo can have, so that `next_id` always fits past it.
+/// Lines with a higher one are malformed.
+const MAX_ID: u64 = u64::MAX - 1;
+/// Starts a [`TodoRef::Position`]
+const POSITION_PREFIX: char = '%';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Active,
+    InProgress,
+    /// Can't go on until something else is done, see [`Record::reason`]
+    Blocked,
+    /// Waiting on someone else, see [`Record::reason`]
+    Waiting,
+    Done,
+    /// Won't be done
+    Cancelled,
+}
+
+impl Status {
+    pub const ALL: [Status; 6] = [
+        Status::Active,
+        Status::InProgress,
+        Status::Blocked,
+        Status::Waiting,
+        Status::Done,
+        Status::Cancelled,
+    ];
+
+    /// How the status is written in todo files and listings
+    pub fn glyph(self) -> &'static str {
+        match self {
+            Status::Active => ACTIVE_TODO,
+            Status::InProgress => IN_PROGRESS_TODO,
+            Status::Blocked => BLOCKED_TODO,
+            Status::Waiting => WAITING_TODO,
+            Status::Done => DONE_TODO,
+            Status::Cancelled => CANCELLED_TODO,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Active => "active",
+            Status::InProgress => "in-progress",
+            Status::Blocked => "blocked",
+            Status::Waiting => "waiting",
+            Status::Done => "done",
+            Status::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Status::ALL.into_iter().find(|status| status.name() == name)
+    }
+
+    fn from_glyph(glyph: &str) -> Option<Self> {
+        Status::ALL
+            .into_iter()
+            .find(|status| status.glyph() == glyph)
+    }
+
+    /// Whether the todo still needs doing, it is neither done nor cancelled
+    pub fn is_open(self) -> bool {
+        !matches!(self, Status::Done | Status::Cancelled)
+    }
+
+    /// Whether a todo with the status can say why, in [`Record::reason`]
+    pub fn has_reason(self) -> bool {
+        matches!(self, Status::Blocked | Status::Waiting)
+    }
+}
+
+impl FromStr for Status {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Status::from_name(&s.to_ascii_lowercase()).ok_or_else(|| {
+            let names = Status::ALL.map(Status::name).join(", ");
+            format!("\"{s}\" is not a status, one of {names}")
+        })
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.glyph())
+    }
+}
+
+/// How urgent a todo is, from A, the most urgent, to Z
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(u8);
+
+impl Priority {
+    pub fn letter(self) -> char {
+        char::from(self.0)
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({})", self.letter())
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.as_bytes() {
+            [letter] if letter.is_ascii_alphabetic() => Ok(Priority(letter.to_ascii_uppercase())),
+            _ => Err(format!("\"{s}\" is not a priority from A to Z")),
+        }
+    }
+}
+
+/// One todo of a directory's list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub id: u64,
+    pub text: String,
+    pub status: Status,
+    /// The git branch checked out when the todo was created
+    pub branch: Option<String>,
+    pub priority: Option<Priority>,
+    pub due: Option<Date>,
+    /// `+tag`s and `@context`s, sorted
+    pub tags: Vec<String>,
+    /// Why a blocked or waiting todo is held up
+    pub reason: Option<String>,
+    /// Seconds since the Unix epoch, `None` for todos created before todo
+    /// kept track
+    pub created_at: Option<u64>,
+    /// When the todo last changed, like `created_at`
+    pub updated_at: Option<u64>,
+    /// When the todo was done or cancelled, `None` while it is open
+    pub completed_at: Option<u64>,
+}
+
+impl Record {
+    /// Parses a record line of a file laid out as `layout`
+    pub(crate) fn parse_line(line: &str, layout: &Layout) -> Option<Self> {
+        let (mut id, mut text, mut status) = (None, None, None);
+        let (mut branch, mut priority, mut due, mut reason) = (None, None, None, None);
+        let (mut created_at, mut updated_at, mut completed_at) = (None, None, None);
+        let mut tags = Vec::new();
+        let mut fields = line.split(COL_SEP_CH);
+        for column in &layout.columns {
+            let field = fields.next()?;
+            match column {
+                Column::Id => id = Some(field.parse::<u64>().ok().filter(|id| *id <= MAX_ID)?),
+                Column::Text if layout.format == 1 => text = Some(field.to_string()),
+                Column::Text => text = Some(unescape(field)?),
+                Column::Status => status = Some(Status::from_glyph(field)?),
+                Column::Branch => branch = Some(unescape(field)?).filter(|b| !b.is_empty()),
+                Column::Priority if field.is_empty() => {}
+                Column::Priority => priority = Some(field.parse().ok()?),
+                Column::Due if field.is_empty() => {}
+                Column::Due => due = Some(field.parse().ok()?),
+                Column::Tags => {
+                    tags = unescape(field)?
+                        .split(TAG_SEP_CH)
+                        .filter(|tag| !tag.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                Column::Reason => reason = Some(unescape(field)?).filter(|r| !r.is_empty()),
+                Column::Created | Column::Updated | Column::Completed if field.is_empty() => {}
+                Column::Created => created_at = Some(field.parse().ok()?),
+                Column::Updated => updated_at = Some(field.parse().ok()?),
+                Column::Completed => completed_at = Some(field.parse().ok()?),
+            }
+        }
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(Record {
+            id: id?,
+            text: text?,
+            status: status?,
+            branch,
+            priority,
+            due,
+            tags,
+            reason,
+            created_at,
+            updated_at,
+            completed_at,
+        })
+    }
+
+    /// Writes the record in the `Column::ALL` layout
+    pub(crate) fn write_line(&self, buf: &mut String) {
+        use std::fmt::Write as _;
+        for (idx, column) in Column::ALL.iter().enumerate() {
+            if idx > 0 {
+                buf.push(COL_SEP_CH);
+            }
+            match column {
+                Column::Id => write!(buf, "{}", self.id).unwrap(),
+                Column::Text => escape_into(buf, &self.text),
+                Column::Status => buf.push_str(self.status.glyph()),
+                Column::Branch => escape_into(buf, self.branch.as_deref().unwrap_or_default()),
+                Column::Priority => {
+                    if let Some(priority) = self.priority {
+                        buf.push(priority.letter());
+                    }
+                }
+                Column::Due => {
+                    if let Some(due) = self.due {
+                        write!(buf, "{due}").unwrap();
+                    }
+                }
+                Column::Tags => escape_into(buf, &self.tags.join(&TAG_SEP_CH.to_string())),
+                Column::Reason => escape_into(buf, self.reason.as_deref().unwrap_or_default()),
+                Column::Created => write_stamp(buf, self.created_at),
+                Column::Updated => write_stamp(buf, self.updated_at),
+                Column::Completed => write_stamp(buf, self.completed_at),
+            }
+        }
+        buf.push('\n');
+    }
+}
+
+/// What `TodoStore::add` needs to know about a todo, the store fills in
+/// the rest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewTodo {
+    pub text: String,
+    pub branch: Option<String>,
+    pub priority: Option<Priority>,
+    pub due: Option<Date>,
+    /// Tags besides those written in the text
+    pub tags: Vec<String>,
+}
+
+impl NewTodo {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            branch: None,
+            priority: None,
+            due: None,
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// A directory's todos and the ID the next one gets.
+///
+/// IDs are never reused, so `next_id` stays ahead of every ID handed out even
+/// after the todos holding the highest ones are deleted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TodoList {
+    pub next_id: u64,
+    pub records: Vec<Record>,
+}
+
+impl TodoList {
+    /// Raises `next_id` past every ID in `records`, lists written before IDs
+    /// were permanent don't record it
+    pub(crate) fn new(next_id: u64, records: Vec<Record>) -> Self {
+        let max_next = records
+            .iter()
+            .map(|r| r.id.saturating_add(1))
+            .max()
+            .unwrap_or(0);
+        Self {
+            next_id: next_id.max(max_next),
+            records,
+        }
+    }
+
+    /// Hands out the next ID, `None` once every ID up to `MAX_ID` was
+    pub(crate) fn allocate_id(&mut self) -> Option<u64> {
+        let id = self.next_id;
+        (id <= MAX_ID).then(|| {
+            self.next_id = id + 1;
+            id
+        })
+    }
+
+    /// Orders the todos for listing: by priority, those without one last,
+    /// and otherwise as they were
+    pub(crate) fn sort(&mut self) {
+        self.records
+            .sort_by_key(|record| (record.priority.is_none(), record.priority));
+    }
+
+    /// Puts `record` back among the others by ID, for a todo that was taken
+    /// out of the list
+    pub(crate) fn insert(&mut self, record: Record) {
+        let idx = self.records.partition_point(|other| other.id < record.id);
+        self.next_id = self.next_id.max(record.id.saturating_add(1));
+        self.records.insert(idx, record);
+    }
+
+    /// Index into `records` of the todo `todo` refers to
+    pub(crate) fn position(&self, todo: TodoRef) -> Option<usize> {
+        match todo {
+            TodoRef::Id(id) => self.records.iter().position(|record| record.id == id),
+            TodoRef::Position(position) => position
+                .checked_sub(1)
+                .filter(|idx| *idx < self.records.len()),
+        }
+    }
+}
+
+/// Picks a todo of a directory, by ID or by where it's listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TodoRef {
+    /// The permanent ID of the todo
+    Id(u64),
+    /// 1-based position in the directory's listing, written `%N`
+    Position(usize),
+}
+
+impl fmt::Display for TodoRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TodoRef::Id(id) => write!(f, "ID {id}"),
+            TodoRef::Position(position) => write!(f, "position {POSITION_PREFIX}{position}"),
+        }
+    }
+}
+
+impl FromStr for TodoRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.strip_prefix(POSITION_PREFIX) {
+            Some(position) => position
+                .parse::<usize>()
+                .ok()
+                .filter(|position| *position > 0)
+                .map(TodoRef::Position)
+                .ok_or_else(|| format!("\"{s}\" is not a position like %1")),
+            None => s
+                .parse::<u64>()
+                .map(TodoRef::Id)
+                .map_err(|_| format!("\"{s}\" is not an ID or a position like %1")),
+        }
+    }
+}
+
+/// A column of the record lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Id,
+    Text,
+    Status,
+    /// Empty when the todo wasn't created in a git worktree
+    Branch,
+    /// The letter of the priority, empty for none
+    Priority,
+    /// `YYYY-MM-DD`, empty for none
+    Due,
+    /// Separated by spaces
+    Tags,
+    /// Empty unless the todo is blocked or waiting and says why
+    Reason,
+    /// Seconds since the Unix epoch, empty for none
+    Created,
+    Updated,
+    Completed,
+}
+
+impl Column {
+    /// Every column, in the order they are written
+    const ALL: [Column; 11] = [
+        Column::Id,
+        Column::Text,
+        Column::Status,
+        Column::Branch,
+        Column::Priority,
+        Column::Due,
+        Column::Tags,
+        Column::Reason,
+        Column::Created,
+        Column::Updated,
+        Column::Completed,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Column::Id => "id",
+            Column::Text => "text",
+            Column::Status => "status",
+            Column::Branch => "branch",
+            Column::Priority => "priority",
+            Column::Due => "due",
+            Column::Tags => "tags",
+            Column::Reason => "reason",
+            Column::Created => "created",
+            Column::Updated => "updated",
+            Column::Completed => "completed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Column::ALL.into_iter().find(|column| column.name() == name)
+    }
+}
+
+/// The columns of files without a `#columns` line, versions 1 and 2. Files
+/// with one can't leave them out.
+const LEGACY_COLUMNS: [Column; 3] = [Column::Id, Column::Text, Column::Status];
+
+/// How the record lines of a todo file are laid out, set by its meta lines
+#[derive(Debug)]
+pub(crate) struct Layout {
+    format: u32,
+    columns: Vec<Column>,
+}
+
+impl Default for Layout {
+    /// A version 1 file, which has no meta lines
+    fn default() -> Self {
+        Self {
+            format: 1,
+            columns: LEGACY_COLUMNS.to_vec(),
+        }
+    }
+}
+
+/// Parses a todo file read from `path`, in any format up to `FORMAT_VERSION`
+pub(crate) fn parse_list(raw_todo_file: &str, path: &Path) -> Result<TodoList> {
+    parse_list_with(raw_todo_file, path, |line_idx, line| {
+        Err(Error::corrupt_record(path, line_idx, line))
+    })
+}
+
+/// Parses a todo file like `parse_list`, but hands the index and content of
+/// lines that can't be parsed to `malformed`, which skips them by returning
+/// `Ok`
+pub(crate) fn parse_list_with(
+    raw_todo_file: &str,
+    path: &Path,
+    malformed: impl FnMut(usize, &str) -> Result<()>,
+) -> Result<TodoList> {
+    let mut next_id = 0;
+    let mut records = Vec::new();
+    read_lines_with(
+        raw_todo_file,
+        path,
+        |key, value| match key {
+            NEXT_ID_META => value.parse().map(|id| next_id = id).ok(),
+            _ => None,
+        },
+        |line, layout| Record::parse_line(line, layout).map(|record| records.push(record)),
+        malformed,
+    )?;
+    Ok(TodoList::new(next_id, records))
+}
+
+/// Reads a file in the todo file format. `#format` and `#columns` lines set
+/// the layout of the lines after them, other meta lines go to `meta` and
+/// the rest to `record`, both returning `None` if they can't parse it.
+/// Lines that can't be parsed go to `malformed`, which skips them by
+/// returning `Ok`.
+pub(crate) fn read_lines_with(
+    raw: &str,
+    path: &Path,
+    mut meta: impl FnMut(&str, &str) -> Option<()>,
+    mut record: impl FnMut(&str, &Layout) -> Option<()>,
+    mut malformed: impl FnMut(usize, &str) -> Result<()>,
+) -> Result<()> {
+    let mut layout = Layout::default();
+    for (line_idx, line) in raw.lines().enumerate() {
+        let parsed = match line.strip_prefix(META_PREFIX) {
+            Some(meta_line) => match meta_line.split_once(COL_SEP_CH) {
+                Some((FORMAT_META, value)) => parse_format(value, path, FORMAT_VERSION)
+                    .transpose()?
+                    .map(|format| layout.format = format),
+                Some((COLUMNS_META, value)) => {
+                    parse_columns(value).map(|columns| layout.columns = columns)
+                }
+                Some((key, value)) => meta(key, value),
+                None => None,
+            },
+            None => record(line, &layout),
+        };
+        if parsed.is_none() {
+            malformed(line_idx, line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the `#format` and `#columns` lines records are written for
+pub(crate) fn write_header(buf: &mut String) {
+    use std::fmt::Write as _;
+    writeln!(
+        buf,
+        "{META_PREFIX}{FORMAT_META}{COL_SEP_CH}{FORMAT_VERSION}"
+    )
+    .unwrap();
+    write!(buf, "{META_PREFIX}{COLUMNS_META}").unwrap();
+    for column in Column::ALL {
+        write!(buf, "{COL_SEP_CH}{}", column.name()).unwrap();
+    }
+    buf.push('\n');
+}
+
+/// Format version of a todo file, without parsing its records
+pub(crate) fn list_format(raw_todo_file: &str) -> u32 {
+    raw_todo_file
+        .lines()
+        .take_while(|line| line.starts_with(META_PREFIX))
+        .find_map(|line| {
+            line[META_PREFIX.len_utf8()..]
+                .strip_prefix(FORMAT_META)?
+                .strip_prefix(COL_SEP_CH)?
+                .parse()
+                .ok()
+        })
+        .unwrap_or(1)
+}
+
+/// Parses the value of the `#format` line of `path`, `None` if it isn't a
+/// version and an error if it is newer than `supported`
+pub(crate) fn parse_format(value: &str, path: &Path, supported: u32) -> Option<Result<u32>> {
+    let version = value.parse::<u32>().ok().filter(|version| *version > 0)?;
+    if version > supported {
+        return Some(Err(Error::UnsupportedVersion {
+            path: path.to_path_buf(),
+            version,
+        }));
+    }
+    Some(Ok(version))
+}
+
+/// Columns named by a `#columns` line. Each column can only be named once,
+/// and only columns added after version 3 can be left out.
+fn parse_columns(value: &str) -> Option<Vec<Column>> {
+    let columns = value
+        .split(COL_SEP_CH)
+        .map(Column::from_name)
+        .collect::<Option<Vec<_>>>()?;
+    let unique = (1..columns.len()).all(|idx| !columns[..idx].contains(&columns[idx]));
+    let complete = LEGACY_COLUMNS.iter().all(|column| columns.contains(column));
+    (unique && complete).then_some(columns)
+}
+
+pub(crate) fn format_list(list: &TodoList) -> String {
+    use std::fmt::Write as _;
+    let mut buf = String::with_capacity(list.records.iter().map(|r| r.text.len() + 16).sum());
+    write_header(&mut buf);
+    writeln!(
+        &mut buf,
+        "{META_PREFIX}{NEXT_ID_META}{COL_SEP_CH}{}",
+        list.next_id
+    )
+    .unwrap();
+    for record in &list.records {
+        record.write_line(&mut buf);
+    }
+    buf
+}
+
+/// Writes `text` to `buf` with backslashes, tabs and line breaks escaped as
+/// `\\`, `\t`, `\n` and `\r`, so it fits in one column of one line
+pub(crate) fn escape_into(buf: &mut String, text: &str) {
+    for ch in text.chars() {
+        match ch {
+            ESCAPE_CH => buf.push_str("\\\\"),
+            '\t' => buf.push_str("\\t"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            ch => buf.push(ch),
+        }
+    }
+}
+
+/// Reverses `escape_into`, `None` on an unknown or unfinished escape
+pub(crate) fn unescape(column: &str) -> Option<String> {
+    let mut text = String::with_capacity(column.len());
+    let mut chars = column.chars();
+    while let Some(ch) = chars.next() {
+        if ch != ESCAPE_CH {
+            text.push(ch);
+            continue;
+        }
+        text.push(match chars.next()? {
+            ESCAPE_CH => ESCAPE_CH,
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+    Some(text)
+}
+
+/// Writes a timestamp column, empty for `None`
+fn write_stamp(buf: &mut String, stamp: Option<u64>) {
+    use std::fmt::Write as _;
+    if let Some(stamp) = stamp {
+        write!(buf, "{stamp}").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "p.tsv";
+
+    fn record(id: u64, text: &str, status: Status) -> Record {
+        Record {
+            id,
+            text: text.to_string(),
+            status,
+            branch: None,
+            priority: None,
+            due: None,
+            tags: Vec::new(),
+            reason: None,
+            created_at: None,
+            updated_at: None,
+            completed_at: None,
+        }
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let mut full = record(2, "multi\nline\twith \\ and \\n", Status::Blocked);
+        full.branch = Some("feature/x".to_string());
+        full.priority = Some(Priority(b'C'));
+        full.due = Date::from_ymd(2026, 11, 1);
+        full.tags = vec!["+bug".to_string(), "@home".to_string()];
+        full.reason = Some("needs\treview".to_string());
+        full.created_at = Some(1_700_000_000);
+        full.completed_at = Some(1_700_000_100);
+        let list = TodoList::new(9, vec![record(0, "", Status::Active), full]);
+        let parsed = parse_list(&format_list(&list), Path::new(PATH)).unwrap();
+        assert_eq!(parsed, list);
+    }
+
+    /// Files written before IDs were permanent have no `#next_id`
+    #[test]
+    fn next_id_stays_past_every_id() {
+        let list = parse_list("4\tfour\t[ ]\n1\tone\t[ ]\n", Path::new(PATH)).unwrap();
+        assert_eq!(list.next_id, 5);
+        let list = parse_list("#next_id\t2\n4\tfour\t[ ]\n", Path::new(PATH)).unwrap();
+        assert_eq!(list.next_id, 5);
+        assert_eq!(parse_list("", Path::new(PATH)).unwrap().next_id, 0);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for raw in [
+            "x\ttext\t[ ]\n",
+            "0\ttext\n",
+            "0\ttext\t[?]\n",
+            "0\ttoo\t[ ]\tmany\n",
+            "#next_id\tmany\n",
+            "#colour\tred\n",
+            "#format\tthree\n",
+            "#format\t0\n",
+            "#format\t2\n0\tdangling \\\t[ ]\n",
+            "18446744073709551615\tlast\t[ ]\n",
+            "18446744073709551616\ttoo big\t[ ]\n",
+        ] {
+            let err = parse_list(raw, Path::new(PATH)).unwrap_err();
+            assert!(matches!(err, Error::CorruptRecord { .. }), "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn ids_run_out_instead_of_overflowing() {
+        let list = parse_list("18446744073709551614\tlast\t[ ]\n", Path::new(PATH));
+        let mut list = list.unwrap();
+        assert_eq!(list.next_id, u64::MAX);
+        assert_eq!(list.allocate_id(), None);
+        let mut list = TodoList::new(MAX_ID - 1, Vec::new());
+        assert_eq!(list.allocate_id(), Some(MAX_ID - 1));
+        assert_eq!(list.allocate_id(), Some(MAX_ID));
+        assert_eq!(list.allocate_id(), None);
+        list.insert(record(MAX_ID, "back", Status::Active));
+        assert_eq!(list.next_id, u64::MAX);
+    }
+
+    #[test]
+    fn parses_ids_and_positions() {
+        assert_eq!("12".parse(), Ok(TodoRef::Id(12)));
+        assert_eq!("%3".parse(), Ok(TodoRef::Position(3)));
+        for bad in ["%0", "%", "-1", "%x", "one"] {
+            assert!(bad.parse::<TodoRef>().is_err(), "{bad:?}");
+        }
+    }
+
+    /// Version 1 files stored text as is, backslashes included
+    #[test]
+    fn version_1_text_is_not_unescaped() {
+        let list = parse_list("0\tC:\\new\t[ ]\n", Path::new(PATH)).unwrap();
+        assert_eq!(list.records[0].text, "C:\\new");
+        let list = parse_list("#format\t2\n0\tC:\\\\new\t[ ]\n", Path::new(PATH)).unwrap();
+        assert_eq!(list.records[0].text, "C:\\new");
+    }
+
+    #[test]
+    fn escaping_is_lossless() {
+        for text in [
+            "",
+            "plain",
+            "\\",
+            "\\n",
+            "a\tb\nc\rd",
+            "\\\t\\\n",
+            "trailing \\",
+        ] {
+            let mut escaped = String::new();
+            escape_into(&mut escaped, text);
+            assert!(!escaped.contains(['\t', '\n', '\r']), "{text:?}");
+            assert_eq!(unescape(&escaped).as_deref(), Some(text));
+        }
+        assert_eq!(unescape("dangling \\"), None);
+        assert_eq!(unescape("\\x"), None);
+    }
+
+    #[test]
+    fn parses_version_1() {
+        let list = parse_list("0\tfirst\t[ ]\n2\ta \\ path\t[✓]\n", Path::new(PATH)).unwrap();
+        assert_eq!(list.next_id, 3);
+        assert_eq!(
+            list.records,
+            [
+                record(0, "first", Status::Active),
+                record(2, "a \\ path", Status::Done),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_version_2() {
+        let raw = "#format\t2\n#next_id\t5\n1\ttwo\\nlines\t[ ]\n";
+        let list = parse_list(raw, Path::new(PATH)).unwrap();
+        assert_eq!(list.next_id, 5);
+        assert_eq!(list.records, [record(1, "two\nlines", Status::Active)]);
+    }
+
+    /// What each version added to a record, applied on top of the versions
+    /// before it
+    type AddColumn = fn(&mut Record);
+
+    /// A record of every column, in the format each version wrote it
+    #[test]
+    fn parses_versions_with_columns() {
+        let fields = [
+            "4",
+            "a\\tb",
+            "[✓]",
+            "main",
+            "B",
+            "2026-11-01",
+            "+bug @home",
+            "needs review",
+            "1700000000",
+            "1700000100",
+            "",
+        ];
+        let mut expected = record(4, "a\tb", Status::Done);
+        let versions: [(u32, usize, AddColumn); 7] = [
+            (3, 3, |_| {}),
+            (4, 4, |r| r.branch = Some("main".to_string())),
+            (5, 5, |r| r.priority = Some(Priority(b'B'))),
+            (6, 6, |r| r.due = Date::from_ymd(2026, 11, 1)),
+            (7, 7, |r| {
+                r.tags = vec!["+bug".to_string(), "@home".to_string()]
+            }),
+            (8, 8, |r| r.reason = Some("needs review".to_string())),
+            (9, 11, |r| {
+                r.created_at = Some(1_700_000_000);
+                r.updated_at = Some(1_700_000_100);
+            }),
+        ];
+        for (version, column_count, added) in versions {
+            added(&mut expected);
+            let columns = Column::ALL[..column_count]
+                .iter()
+                .map(|c| c.name())
+                .collect::<Vec<_>>()
+                .join("\t");
+            let raw = format!(
+                "#format\t{version}\n#columns\t{columns}\n#next_id\t7\n{}\n",
+                fields[..column_count].join("\t")
+            );
+            let list = parse_list(&raw, Path::new(PATH)).unwrap();
+            assert_eq!(list.next_id, 7, "version {version}");
+            assert_eq!(list.records, [expected.clone()], "version {version}");
+        }
+    }
+
+    #[test]
+    fn columns_can_come_in_any_order() {
+        let raw = "#format\t3\n#columns\tstatus\tid\ttext\n[✓]\t3\tthree\n";
+        let list = parse_list(raw, Path::new(PATH)).unwrap();
+        assert_eq!(list.records, [record(3, "three", Status::Done)]);
+    }
+
+    #[test]
+    fn priorities_order_the_list() {
+        assert_eq!("b".parse(), Ok(Priority(b'B')));
+        for bad in ["", "1", "AB", "é"] {
+            assert!(bad.parse::<Priority>().is_err(), "{bad:?}");
+        }
+        let with = |id, priority: Option<u8>| Record {
+            priority: priority.map(Priority),
+            ..record(id, "", Status::Active)
+        };
+        let mut list = TodoList::new(
+            0,
+            vec![
+                with(0, None),
+                with(1, Some(b'B')),
+                with(2, None),
+                with(3, Some(b'A')),
+            ],
+        );
+        list.sort();
+        let ids: Vec<u64> = list.records.iter().map(|r| r.id).collect();
+        assert_eq!(ids, [3, 1, 0, 2]);
+    }
+
+    #[test]
+    fn statuses_have_a_glyph_and_a_name() {
+        for status in Status::ALL {
+            assert_eq!(Status::from_glyph(status.glyph()), Some(status));
+            assert_eq!(status.name().parse(), Ok(status));
+        }
+        assert_eq!("In-Progress".parse(), Ok(Status::InProgress));
+        assert!("started".parse::<Status>().is_err());
+        let open: Vec<bool> = Status::ALL.map(Status::is_open).to_vec();
+        assert_eq!(open, [true, true, true, true, false, false]);
+    }
+
+    #[test]
+    fn rejects_newer_versions_and_bad_columns() {
+        let newer = format!("#format\t{}\n", FORMAT_VERSION + 1);
+        assert!(matches!(
+            parse_list(&newer, Path::new(PATH)),
+            Err(Error::UnsupportedVersion { .. })
+        ));
+        for raw in [
+            "#format\t3\n#columns\tid\ttext\n",
+            "#format\t3\n#columns\tid\ttext\tstatus\tid\n",
+            "#format\t3\n#columns\tid\ttext\tstatus\tcolour\n",
+            "#format\t3\n#columns\tstatus\tid\ttext\n0\tzero\t[ ]\n",
+        ] {
+            assert!(parse_list(raw, Path::new(PATH)).is_err(), "{raw:?}");
+        }
+    }
+}
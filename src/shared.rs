@@ -0,0 +1,88 @@
+//! shared is a todo file kept in the directory it belongs to, records sorted
+//! by ID and no `#next_id` line, so version control can merge it
+
+use std::{
+    fs::{OpenOptions, read_to_string, remove_file},
+    hash::{BuildHasher, RandomState},
+    io::{ErrorKind, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Error, Record, Result, TodoList,
+    files::write_atomic,
+    key::key_path,
+    record::{parse_list, write_header},
+};
+
+pub(crate) const SHARED_NAME: &str = ".todo.tsv";
+const GIT_ATTRIBUTES_NAME: &str = ".gitattributes";
+/// Has git keep the lines of both sides when two branches add todos next to
+/// each other, which a sorted file with one todo per line merges fine with
+const MERGE_ATTRIBUTE: &str = "merge=union";
+/// IDs handed out in shared files, random so that todos added on two
+/// branches don't get the same one
+pub(crate) const SHARED_IDS: Range<u64> = 100_000..1_000_000;
+
+/// The shared todo file of `dir`, if it has one
+pub(crate) fn find(dir: &str) -> Option<PathBuf> {
+    let path = key_path(dir).join(SHARED_NAME);
+    path.is_file().then_some(path)
+}
+
+pub(crate) fn load(path: &Path) -> Result<TodoList> {
+    let raw = read_to_string(path).map_err(Error::io(path))?;
+    parse_list(&raw, path)
+}
+
+/// Writes `list` to the shared todo file of `dir`
+pub(crate) fn save(dir: &str, list: &TodoList) -> Result<()> {
+    let mut records = list.records.iter().collect::<Vec<&Record>>();
+    records.sort_by_key(|record| record.id);
+    let mut buf = String::with_capacity(records.iter().map(|r| r.text.len() + 16).sum());
+    write_header(&mut buf);
+    for record in records {
+        record.write_line(&mut buf);
+    }
+    write_atomic(&mut key_path(dir), SHARED_NAME, buf.as_bytes())
+}
+
+/// Sets the union merge driver for the shared todo file in the
+/// `.gitattributes` of `dir`, unless it already has attributes for it
+pub(crate) fn set_merge_attribute(dir: &str) -> Result<()> {
+    let path = key_path(dir).join(GIT_ATTRIBUTES_NAME);
+    let attributes = match read_to_string(&path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        other => other.map_err(Error::io(&path))?,
+    };
+    let has_pattern = attributes
+        .lines()
+        .any(|line| line.split_whitespace().next() == Some(SHARED_NAME));
+    if has_pattern {
+        return Ok(());
+    }
+    let separator = match attributes.is_empty() || attributes.ends_with('\n') {
+        true => "",
+        false => "\n",
+    };
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{separator}{SHARED_NAME} {MERGE_ATTRIBUTE}"))
+        .map_err(Error::io(&path))
+}
+
+pub(crate) fn remove(path: &Path) -> Result<()> {
+    remove_file(path).map_err(Error::io(path))
+}
+
+/// A random ID from `SHARED_IDS` no todo of `list` has
+pub(crate) fn allocate_id(list: &TodoList) -> u64 {
+    let state = RandomState::new();
+    (0u64..)
+        .map(|seed| SHARED_IDS.start + state.hash_one(seed) % (SHARED_IDS.end - SHARED_IDS.start))
+        .find(|id| list.records.iter().all(|record| record.id != *id))
+        .expect("a shared list has room for another ID")
+}
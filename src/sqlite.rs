@@ -0,0 +1,359 @@
+use std::{
+    fs::{copy, rename},
+    path::{Path, PathBuf},
+};
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+
+use crate::{
+    Error, Finding, Problem, Record, Result, Status, TodoList,
+    files::unused_path,
+    storage::{SQLITE_NAME, Storage},
+};
+const SQLITE_BACKUP_EXT: &str = "bak";
+
+/// Schema changes in order, `PRAGMA user_version` counts how many were applied
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE IF NOT EXISTS dirs (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS todos (
+        dir_id INTEGER NOT NULL REFERENCES dirs(id),
+        id INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        status TEXT NOT NULL,
+        PRIMARY KEY (dir_id, id)
+    );
+    ",
+    "ALTER TABLE dirs ADD COLUMN next_id INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE todos ADD COLUMN branch TEXT;",
+    "ALTER TABLE todos ADD COLUMN priority TEXT;",
+    "ALTER TABLE todos ADD COLUMN due TEXT;",
+    "ALTER TABLE todos ADD COLUMN tags TEXT NOT NULL DEFAULT '';",
+    "ALTER TABLE todos ADD COLUMN reason TEXT;",
+    "
+    ALTER TABLE todos ADD COLUMN created_at INTEGER;
+    ALTER TABLE todos ADD COLUMN updated_at INTEGER;
+    ALTER TABLE todos ADD COLUMN completed_at INTEGER;
+    ",
+];
+
+/// Every todo list in one embedded SQLite database, `todo.sqlite3`
+#[derive(Debug)]
+pub(crate) struct SqliteStorage {
+    path: PathBuf,
+    conn: Connection,
+}
+
+impl SqliteStorage {
+    /// Opens the database of the store in `dir`. A missing database is only
+    /// created when `create` is set, otherwise it reads as empty.
+    ///
+    /// A database with an older schema is copied to
+    /// `todo.sqlite3.v<version>.bak` and upgraded, but only when `create` is
+    /// set, as readers must not change the store. Opened to read, such a
+    /// database is an `Error::Conflict`.
+    pub(crate) fn open(dir: &Path, create: bool) -> Result<Self> {
+        let path = dir.join(SQLITE_NAME);
+        let conn = if create || path.is_file() {
+            Connection::open(&path)
+        } else {
+            Connection::open_in_memory()
+        }
+        .map_err(Error::sqlite(&path))?;
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(Error::sqlite(&path))?;
+        if version > MIGRATIONS.len() {
+            return Err(Error::UnsupportedVersion {
+                path,
+                version: version as u32,
+            });
+        }
+        // the in-memory database of a missing one is set up like a new one
+        let old_file = version < MIGRATIONS.len() && path.is_file();
+        if old_file && !create {
+            return Err(Error::Conflict(format!(
+                "{path:?} has schema version {version} of {}, run `todo migrate` or \
+                 another command that changes todos to upgrade it",
+                MIGRATIONS.len()
+            )));
+        }
+        if old_file && version > 0 {
+            let backup_path = path.with_added_extension(format!("v{version}.{SQLITE_BACKUP_EXT}"));
+            copy(&path, &backup_path).map_err(Error::io(&path))?;
+        }
+        migrate_schema(&conn, version).map_err(Error::sqlite(&path))?;
+        Ok(Self { path, conn })
+    }
+
+    /// Row ID and next todo ID of `dir`
+    fn dir_row(&self, dir: &str) -> rusqlite::Result<Option<(i64, i64)>> {
+        self.conn
+            .query_row(
+                "SELECT id, next_id FROM dirs WHERE path = ?1",
+                [dir],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn dirs(&mut self) -> Result<Vec<String>> {
+        let path = &self.path;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path FROM dirs ORDER BY id")
+            .map_err(Error::sqlite(path))?;
+        stmt.query_map([], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(Error::sqlite(path))
+    }
+
+    fn load(&mut self, dir: &str) -> Result<Option<TodoList>> {
+        let Some((dir_id, next_id)) = self.dir_row(dir).map_err(Error::sqlite(&self.path))? else {
+            return Ok(None);
+        };
+        let path = &self.path;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, text, status, branch, priority, due, tags, reason, created_at, updated_at, completed_at FROM todos WHERE dir_id = ?1 ORDER BY id")
+            .map_err(Error::sqlite(path))?;
+        let rows = stmt
+            .query_map([dir_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    row.get::<_, String>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    [
+                        row.get::<_, Option<i64>>(8)?,
+                        row.get::<_, Option<i64>>(9)?,
+                        row.get::<_, Option<i64>>(10)?,
+                    ],
+                ))
+            })
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+            .map_err(Error::sqlite(path))?;
+        let records = rows
+            .into_iter()
+            .enumerate()
+            .map(
+                |(idx, (id, text, status, branch, priority, due, tags, reason, stamps))| {
+                    let status = Status::from_name(&status)
+                        .ok_or_else(|| Error::corrupt_record(path, idx, &status))?;
+                    let priority = priority
+                        .map(|priority| {
+                            priority
+                                .parse()
+                                .map_err(|_| Error::corrupt_record(path, idx, &priority))
+                        })
+                        .transpose()?;
+                    let due = due
+                        .map(|due| {
+                            due.parse()
+                                .map_err(|_| Error::corrupt_record(path, idx, &due))
+                        })
+                        .transpose()?;
+                    Ok(Record {
+                        id: id as u64,
+                        text,
+                        status,
+                        branch,
+                        priority,
+                        due,
+                        tags: tags.split_whitespace().map(str::to_string).collect(),
+                        reason,
+                        created_at: stamps[0].map(|stamp| stamp as u64),
+                        updated_at: stamps[1].map(|stamp| stamp as u64),
+                        completed_at: stamps[2].map(|stamp| stamp as u64),
+                    })
+                },
+            )
+            .collect::<Result<_>>()?;
+        Ok(Some(TodoList::new(next_id as u64, records)))
+    }
+
+    fn save(&mut self, dir: &str, list: &TodoList) -> Result<()> {
+        let path = self.path.as_path();
+        let tx = self.conn.transaction().map_err(Error::sqlite(path))?;
+        save_in(&tx, dir, list).map_err(Error::sqlite(path))?;
+        tx.commit().map_err(Error::sqlite(path))
+    }
+
+    fn remove(&mut self, dir: &str) -> Result<()> {
+        let path = self.path.as_path();
+        let tx = self.conn.transaction().map_err(Error::sqlite(path))?;
+        tx.execute(
+            "DELETE FROM todos WHERE dir_id = (SELECT id FROM dirs WHERE path = ?1)",
+            [dir],
+        )
+        .and_then(|_| tx.execute("DELETE FROM dirs WHERE path = ?1", [dir]))
+        .map_err(Error::sqlite(path))?;
+        tx.commit().map_err(Error::sqlite(path))
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        self.conn
+            .execute("UPDATE dirs SET path = ?1 WHERE path = ?2", [to, from])
+            .map(drop)
+            .map_err(Error::sqlite(&self.path))
+    }
+
+    fn retire(self: Box<Self>) -> Result<PathBuf> {
+        let Self { path, conn } = *self;
+        conn.close().map_err(|(_conn, e)| Error::sqlite(&path)(e))?;
+        let backup_path = unused_path(&path, SQLITE_BACKUP_EXT);
+        rename(&path, &backup_path).map_err(Error::io(&path))?;
+        Ok(backup_path)
+    }
+}
+
+/// Runs SQLite's integrity check on the database of the store in `dir`
+pub(crate) fn integrity_check(dir: &Path) -> Result<Vec<Finding>> {
+    let path = dir.join(SQLITE_NAME);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(Error::sqlite(&path))?;
+    let mut stmt = conn
+        .prepare("PRAGMA integrity_check")
+        .map_err(Error::sqlite(&path))?;
+    let messages = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+        .map_err(Error::sqlite(&path))?;
+    Ok(messages
+        .into_iter()
+        .filter(|message| message != "ok")
+        .map(|message| Finding {
+            problem: Problem::Database(message),
+            repair: None,
+        })
+        .collect())
+}
+
+/// Applies the `MIGRATIONS` after the first `applied`
+fn migrate_schema(conn: &Connection, applied: usize) -> rusqlite::Result<()> {
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        conn.execute_batch(&format!(
+            "BEGIN; {migration} PRAGMA user_version = {}; COMMIT;",
+            version + 1
+        ))?;
+    }
+    Ok(())
+}
+
+fn save_in(tx: &Connection, dir: &str, list: &TodoList) -> rusqlite::Result<()> {
+    tx.execute("INSERT OR IGNORE INTO dirs (path) VALUES (?1)", [dir])?;
+    tx.execute(
+        "UPDATE dirs SET next_id = ?1 WHERE path = ?2",
+        params![list.next_id as i64, dir],
+    )?;
+    let dir_id: i64 = tx.query_row("SELECT id FROM dirs WHERE path = ?1", [dir], |row| {
+        row.get(0)
+    })?;
+    tx.execute("DELETE FROM todos WHERE dir_id = ?1", [dir_id])?;
+    let mut insert = tx.prepare(
+        "INSERT INTO todos (dir_id, id, text, status, branch, priority, due, tags, reason,
+                            created_at, updated_at, completed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+    )?;
+    for record in &list.records {
+        insert.execute(params![
+            dir_id,
+            record.id as i64,
+            record.text,
+            record.status.name(),
+            record.branch,
+            record
+                .priority
+                .map(|priority| priority.letter().to_string()),
+            record.due.map(|due| due.to_string()),
+            record.tags.join(" "),
+            record.reason,
+            record.created_at.map(|stamp| stamp as i64),
+            record.updated_at.map(|stamp| stamp as i64),
+            record.completed_at.map(|stamp| stamp as i64)
+        ])?;
+    }
+    Ok(())
+}
+
+impl Error {
+    /// Wraps a database error on `path`, for `map_err`
+    pub(crate) fn sqlite(path: &Path) -> impl FnOnce(rusqlite::Error) -> Error {
+        move |source| Error::Sqlite {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    /// A database with the schema of the first `version` migrations and one
+    /// todo in `/p`
+    fn old_database(dir: &Path, version: usize) {
+        let conn = Connection::open(dir.join(SQLITE_NAME)).unwrap();
+        for migration in &MIGRATIONS[..version] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.execute_batch(&format!(
+            "INSERT INTO dirs (id, path) VALUES (1, '/p');
+             INSERT INTO todos (dir_id, id, text, status) VALUES (1, 0, 'old', 'active');
+             PRAGMA user_version = {version};"
+        ))
+        .unwrap();
+    }
+
+    fn user_version(dir: &Path) -> usize {
+        let conn = Connection::open(dir.join(SQLITE_NAME)).unwrap();
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn readers_leave_old_schemas_alone() {
+        let temp = TempDir::new("sqlite-read-old");
+        old_database(temp.path(), 2);
+        let err = SqliteStorage::open(temp.path(), false).unwrap_err();
+        assert!(matches!(err, Error::Conflict(_)), "{err}");
+        assert!(err.to_string().contains("todo migrate"), "{err}");
+        assert_eq!(user_version(temp.path()), 2);
+        assert!(!temp.path().join("todo.sqlite3.v2.bak").exists());
+    }
+
+    #[test]
+    fn writers_upgrade_old_schemas_after_a_backup() {
+        let temp = TempDir::new("sqlite-write-old");
+        old_database(temp.path(), 2);
+        let mut storage = SqliteStorage::open(temp.path(), true).unwrap();
+        let list = storage.load("/p").unwrap().unwrap();
+        assert_eq!(list.records[0].text, "old");
+        assert_eq!(user_version(temp.path()), MIGRATIONS.len());
+        assert!(temp.path().join("todo.sqlite3.v2.bak").is_file());
+        drop(storage);
+        // upgraded, it opens to read as well
+        assert!(SqliteStorage::open(temp.path(), false).is_ok());
+    }
+
+    #[test]
+    fn a_missing_database_reads_as_empty() {
+        let temp = TempDir::new("sqlite-missing");
+        let mut storage = SqliteStorage::open(temp.path(), false).unwrap();
+        assert_eq!(storage.dirs().unwrap(), Vec::<String>::new());
+        assert!(!temp.path().join(SQLITE_NAME).exists());
+    }
+}
@@ -0,0 +1,74 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use crate::{Result, TodoList, store::Migration};
+
+/// Database file of the SQLite backend
+pub(crate) const SQLITE_NAME: &str = "todo.sqlite3";
+
+/// Where the todo lists of a store are kept.
+///
+/// A backend only loads and saves whole lists, [`crate::TodoStore`] does
+/// everything else on top of it.
+pub trait Storage {
+    /// Directories that have a todo list, in the order they were first added
+    fn dirs(&mut self) -> Result<Vec<String>>;
+
+    /// Todo list of `dir`, `None` if it has none
+    fn load(&mut self, dir: &str) -> Result<Option<TodoList>>;
+
+    /// Replaces the todo list of `dir`, creating it if needed
+    fn save(&mut self, dir: &str, list: &TodoList) -> Result<()>;
+
+    /// Deletes the todo list of `dir`
+    fn remove(&mut self, dir: &str) -> Result<()>;
+
+    /// Moves the todo list of `from` to `to`, which must not have one
+    fn rename(&mut self, from: &str, to: &str) -> Result<()>;
+
+    /// Upgrades data written by older versions
+    fn migrate(&mut self) -> Result<Vec<Migration>> {
+        Ok(Vec::new())
+    }
+
+    /// Moves the backend's files aside, so the store is no longer detected as
+    /// this backend, and returns where they went
+    fn retire(self: Box<Self>) -> Result<PathBuf>;
+}
+
+/// The storage backends a store can use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// `dirmap.tsv` plus one `<hash>.tsv` per directory
+    Tsv,
+    /// One embedded SQLite database, needs the `sqlite` feature
+    Sqlite,
+}
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Tsv => "tsv",
+            Backend::Sqlite => "sqlite",
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "tsv" => Ok(Backend::Tsv),
+            "sqlite" => Ok(Backend::Sqlite),
+            other => Err(format!(
+                "unknown backend \"{other}\", expected tsv or sqlite"
+            )),
+        }
+    }
+}
@@ -0,0 +1,2108 @@
+use std::{
+    fs::{File, create_dir_all},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    Archived, Backend, Config, Date, Error, Finding, JournalEntry, NewTodo, Operation, Priority,
+    Record, Result, Status, TodoList, TodoRef,
+    alias::Aliases,
+    archive,
+    files::{lock_store, unix_now, with_pushed},
+    fsck, journal,
+    key::{dir_key, display_key, key_path, path_to_key},
+    shared,
+    storage::{SQLITE_NAME, Storage},
+    tags_in,
+    tsv::TsvStorage,
+};
+
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a [`TodoStore`] is opened, readers share the store lock, writers hold it
+/// exclusively. Changing a store opened to read returns `Error::ReadOnly`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// The todo lists of every directory, kept in one store directory.
+///
+/// Only the lists live in the [`Storage`] backend. The aliases, archive,
+/// settings and journal are TSV files next to it whatever the backend, so
+/// they survive `todo storage convert` untouched.
+///
+/// The store stays locked for as long as the `TodoStore` lives, so a
+/// read-modify-write through it can't interleave with another process.
+pub struct TodoStore {
+    dir: PathBuf,
+    access: Access,
+    backend: Backend,
+    storage: Box<dyn Storage>,
+    aliases: Aliases,
+    config: Config,
+    _lock: File,
+}
+
+/// A list `todo gc` offers to drop, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleList {
+    pub dir: String,
+    pub reason: StaleReason,
+    pub records: Vec<Record>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleReason {
+    /// The directory doesn't exist anymore
+    DirGone,
+    /// Every todo was deleted
+    Empty,
+    /// Every todo is done or cancelled
+    AllDone,
+}
+
+/// What `TodoStore::migrate` did to one directory's list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Migration {
+    /// The todo file was renamed from its `DefaultHasher` name
+    Renamed {
+        dir: String,
+        from: String,
+        to: String,
+    },
+    /// The new name is taken by another file, the entry was left alone
+    Conflict { dir: String, existing: PathBuf },
+    /// The store was copied to this directory before being upgraded
+    BackedUp(PathBuf),
+    /// `file` was rewritten from format version `from` to `to`
+    Upgraded { file: PathBuf, from: u32, to: u32 },
+    /// The list was moved from a non-canonical key to its canonical one
+    Canonicalized { from: String, to: String },
+    /// The canonical key of `dir` already has a list of its own, both were
+    /// left alone
+    Duplicate { dir: String, canonical: String },
+}
+
+impl TodoStore {
+    /// Opens the store in `dir`, creating it if needed, waiting up to
+    /// `lock_timeout` for other processes to release it.
+    ///
+    /// Without a `backend` the one the store already has data for is used,
+    /// TSV for a new store.
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        backend: Option<Backend>,
+        access: Access,
+        lock_timeout: Duration,
+    ) -> Result<Self> {
+        let mut dir = dir.into();
+        dir.as_mut_os_string().reserve(64);
+        if !dir.exists() {
+            create_dir_all(&dir).map_err(Error::io(&dir))?;
+        }
+        let lock = lock_store(&mut dir, access, lock_timeout)?;
+        let backend = backend.unwrap_or_else(|| detect_backend(&mut dir));
+        let storage = open_storage(&dir, backend, access)?;
+        let aliases = Aliases::load(&mut dir)?;
+        let config = Config::load(&mut dir)?;
+        Ok(Self {
+            dir,
+            access,
+            backend,
+            storage,
+            aliases,
+            config,
+            _lock: lock,
+        })
+    }
+
+    /// The store directory
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// The directory whose list `dir` uses: what it is an alias of, else
+    /// `dir` itself. Every method taking a `dir` resolves it like this.
+    pub fn resolve<'a>(&'a self, dir: &'a str) -> &'a str {
+        self.aliases.resolve(dir)
+    }
+
+    /// (alias, dir it points at) pairs, in the order they were added
+    pub fn aliases(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases.iter()
+    }
+
+    /// Makes `alias` use the todo list of `dir`. `alias` can't have a list of
+    /// its own.
+    pub fn add_alias(&mut self, alias: &str, dir: &str) -> Result<()> {
+        self.check_writable()?;
+        let dir = self.resolve(dir).to_string();
+        if alias == dir {
+            return Err(Error::Conflict(format!(
+                "\"{}\" can't be an alias of itself",
+                display_key(alias)
+            )));
+        }
+        if self.load_list(alias)?.is_some() {
+            return Err(Error::Conflict(format!(
+                "\"{}\" has a todo list of its own",
+                display_key(alias)
+            )));
+        }
+        self.aliases.insert(alias, &dir);
+        self.aliases.save(&mut self.dir)
+    }
+
+    /// Removes `alias`, returning the directory it pointed at
+    pub fn remove_alias(&mut self, alias: &str) -> Result<String> {
+        self.check_writable()?;
+        let dir = self.aliases.remove(alias).ok_or_else(|| {
+            Error::NotFound(format!("\"{}\" is not an alias", display_key(alias)))
+        })?;
+        self.aliases.save(&mut self.dir)?;
+        Ok(dir)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Changes the setting `key`, see [`Config::set`]
+    pub fn set_config(&mut self, key: &str, value: &str) -> Result<()> {
+        self.check_writable()?;
+        self.config.set(key, value)?;
+        self.config.save(&mut self.dir)
+    }
+
+    /// Todos of `dir` in listing order, `None` if it never had any
+    pub fn list(&mut self, dir: &str) -> Result<Option<Vec<Record>>> {
+        let dir = self.resolve(dir).to_string();
+        Ok(self.load_list(&dir)?.map(|list| list.records))
+    }
+
+    /// Todos of `dir` together with those of its parent directories when
+    /// `up` is set and those of the directories below it when `down` is,
+    /// top-down in path order. Directories without todos are left out.
+    pub fn list_tree(
+        &mut self,
+        dir: &str,
+        up: bool,
+        down: bool,
+    ) -> Result<Vec<(String, Vec<Record>)>> {
+        let dir = self.resolve(dir).to_string();
+        let path = key_path(&dir);
+        let mut dirs = vec![dir.clone()];
+        if up {
+            dirs.extend(
+                path.ancestors()
+                    .skip(1)
+                    .map(|ancestor| self.resolve(&path_to_key(ancestor.as_os_str())).to_string()),
+            );
+        }
+        if down {
+            dirs.extend(
+                self.storage
+                    .dirs()?
+                    .into_iter()
+                    .filter(|other| *other != dir && key_path(other).starts_with(&path)),
+            );
+        }
+        dirs.sort_by_cached_key(|dir| key_path(dir));
+        dirs.dedup();
+        let mut lists = Vec::new();
+        for dir in dirs {
+            if let Some(list) = self.load_list(&dir)? {
+                lists.push((dir, list.records));
+            }
+        }
+        Ok(lists)
+    }
+
+    /// Todos of every directory, in the order they were first added
+    pub fn list_all(&mut self) -> Result<Vec<(String, Vec<Record>)>> {
+        self.storage
+            .dirs()?
+            .into_iter()
+            .map(|dir| {
+                let list = self.load_list(&dir)?.unwrap_or_default();
+                Ok((dir, list.records))
+            })
+            .collect()
+    }
+
+    /// Adds an active todo to `dir`, rejecting text already in its list
+    pub fn add(&mut self, dir: &str, todo: NewTodo) -> Result<Record> {
+        self.check_writable()?;
+        let dir = &self.resolve(dir).to_string();
+        let mut list = self.load_list(dir)?.unwrap_or_default();
+        if let Some(same) = list.records.iter().find(|record| record.text == todo.text) {
+            return Err(Error::AlreadyExists {
+                text: todo.text,
+                id: same.id,
+            });
+        }
+        let id = match shared::find(dir) {
+            Some(_) => shared::allocate_id(&list),
+            None => list.allocate_id().ok_or_else(|| {
+                Error::Conflict(format!(
+                    "\"{}\" has used up every todo ID",
+                    display_key(dir)
+                ))
+            })?,
+        };
+        let now = unix_now();
+        let record = Record {
+            id,
+            tags: with_text_tags(todo.tags, &todo.text),
+            text: todo.text,
+            status: Status::Active,
+            reason: None,
+            branch: todo.branch,
+            priority: todo.priority,
+            due: todo.due,
+            created_at: Some(now),
+            updated_at: Some(now),
+            completed_at: None,
+        };
+        list.records.push(record.clone());
+        list.sort();
+        self.save_list(dir, &list)?;
+        self.journal(Operation::New, dir, None, Some(record.clone()))?;
+        Ok(record)
+    }
+
+    /// Replaces the text of `todo` in `dir`. The tags written in the old
+    /// text go with it, those in the new text are added.
+    pub fn update(&mut self, dir: &str, todo: TodoRef, text: &str) -> Result<Record> {
+        self.modify(dir, todo, Operation::Update, |record| {
+            let old_tags = tags_in(&record.text).collect::<Vec<_>>();
+            record.tags.retain(|tag| !old_tags.contains(&tag.as_str()));
+            record.tags = with_text_tags(std::mem::take(&mut record.tags), text);
+            record.text = text.to_string();
+        })
+    }
+
+    /// Sets the status of `todo` in `dir`, with `reason` saying why it is
+    /// blocked or waiting. Other statuses have no reason; passing one is an
+    /// `Error::Conflict`. Finishing the todo stamps when, reopening it clears
+    /// that.
+    pub fn set_status(
+        &mut self,
+        dir: &str,
+        todo: TodoRef,
+        status: Status,
+        reason: Option<String>,
+    ) -> Result<Record> {
+        if reason.is_some() && !status.has_reason() {
+            return Err(Error::Conflict(format!(
+                "only blocked and waiting todos have a reason, not {} ones",
+                status.name()
+            )));
+        }
+        self.modify(dir, todo, Operation::Status, |record| {
+            record.completed_at = match status.is_open() {
+                true => None,
+                false => record.completed_at.or(Some(unix_now())),
+            };
+            record.status = status;
+            record.reason = reason;
+        })
+    }
+
+    /// Sets or, with `None`, clears the due date of `todo` in `dir`
+    pub fn set_due(&mut self, dir: &str, todo: TodoRef, due: Option<Date>) -> Result<Record> {
+        self.modify(dir, todo, Operation::Due, |record| record.due = due)
+    }
+
+    /// Open todos of every directory due on or before `until`, soonest
+    /// first
+    pub fn due_until(&mut self, until: Date) -> Result<Vec<(String, Record)>> {
+        let mut due = Vec::new();
+        for (dir, records) in self.list_all()? {
+            due.extend(
+                records
+                    .into_iter()
+                    .filter(|record| {
+                        record.status.is_open() && record.due.is_some_and(|due| due <= until)
+                    })
+                    .map(|record| (dir.clone(), record)),
+            );
+        }
+        due.sort_by_key(|(_dir, record)| record.due);
+        Ok(due)
+    }
+
+    /// Sets or, with `None`, clears the priority of `todo` in `dir`
+    pub fn set_priority(
+        &mut self,
+        dir: &str,
+        todo: TodoRef,
+        priority: Option<Priority>,
+    ) -> Result<Record> {
+        self.modify(dir, todo, Operation::Priority, |record| {
+            record.priority = priority
+        })
+    }
+
+    /// The todo `todo` of `dir`
+    pub fn get(&mut self, dir: &str, todo: TodoRef) -> Result<Record> {
+        let dir = &self.resolve(dir).to_string();
+        let list = self.load_existing(dir)?;
+        let idx = list.position(todo).ok_or_else(|| no_record(dir, todo))?;
+        Ok(list.records[idx].clone())
+    }
+
+    /// Deletes `todo` from `dir`, moving it to the store's archive when
+    /// `archive` is set. The other todos keep their IDs and the deleted ID
+    /// is never handed out again.
+    ///
+    /// Without `archive` the todo is purged: it is also scrubbed from the
+    /// journal, and neither the purge nor earlier changes to the todo can be
+    /// undone.
+    ///
+    /// Deleting an open todo (one that is neither done nor cancelled) first
+    /// asks `confirm_open`, returning `Error::Cancelled` if it says no.
+    pub fn delete(
+        &mut self,
+        dir: &str,
+        todo: TodoRef,
+        archive: bool,
+        confirm_open: impl FnOnce(&Record) -> bool,
+    ) -> Result<Record> {
+        self.check_writable()?;
+        let dir = &self.resolve(dir).to_string();
+        let mut list = self.load_existing(dir)?;
+        let idx = list.position(todo).ok_or_else(|| no_record(dir, todo))?;
+        if list.records[idx].status.is_open() && !confirm_open(&list.records[idx]) {
+            return Err(Error::Cancelled);
+        }
+        let deleted = list.records.remove(idx);
+        // archived first, so a failed save leaves the todo in both places
+        // rather than in neither
+        if archive {
+            self.archive(dir, deleted.clone())?;
+        }
+        self.save_list(dir, &list)?;
+        match archive {
+            true => self.journal(Operation::Delete, dir, Some(deleted.clone()), None)?,
+            false => {
+                journal::scrub(&mut self.dir, dir, deleted.id)?;
+                self.journal(Operation::Purge, dir, None, None)?;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Every archived todo, oldest first
+    pub fn archived(&mut self) -> Result<Vec<Archived>> {
+        archive::load(&mut self.dir)
+    }
+
+    /// Moves the archived todo with ID `id` of `dir` back into its list
+    pub fn restore(&mut self, dir: &str, id: u64) -> Result<Record> {
+        self.check_writable()?;
+        let dir = &self.resolve(dir).to_string();
+        let mut list = self.load_list(dir)?.unwrap_or_default();
+        if list.records.iter().any(|other| other.id == id) {
+            return Err(Error::Conflict(format!(
+                "\"{}\" already has a todo with ID {id}",
+                display_key(dir)
+            )));
+        }
+        let record = self
+            .unarchive(dir, |record| record.id == id)?
+            .ok_or_else(|| {
+                Error::NotFound(format!(
+                    "no archived todo with ID {id} @ \"{}\"",
+                    display_key(dir)
+                ))
+            })?;
+        list.insert(record.clone());
+        self.save_list(dir, &list)?;
+        self.journal(Operation::Restore, dir, None, Some(record.clone()))?;
+        Ok(record)
+    }
+
+    /// Drops the archived todos that were archived more than `max_age`
+    /// seconds ago, returning them
+    pub fn expire_archive(&mut self, max_age: u64) -> Result<Vec<Archived>> {
+        self.check_writable()?;
+        let cutoff = unix_now().saturating_sub(max_age);
+        let (expired, kept) = archive::load(&mut self.dir)?
+            .into_iter()
+            .partition::<Vec<_>, _>(|entry| entry.archived_at < cutoff);
+        if !expired.is_empty() {
+            archive::save(&mut self.dir, &kept)?;
+        }
+        Ok(expired)
+    }
+
+    /// Recent changes to todos in every directory, oldest first
+    pub fn history(&mut self) -> Result<Vec<JournalEntry>> {
+        journal::load(&mut self.dir)
+    }
+
+    /// Reverts the latest change that isn't undone yet and returns it. Fails
+    /// if the todo was changed again since.
+    pub fn undo(&mut self) -> Result<JournalEntry> {
+        self.replay(Operation::Undo)
+    }
+
+    /// Applies the latest undone change again and returns it. Any other
+    /// change since the undo makes it final.
+    pub fn redo(&mut self) -> Result<JournalEntry> {
+        self.replay(Operation::Redo)
+    }
+
+    /// Moves the todo list of `dir` into a `.todo.tsv` file in the directory
+    /// itself, to be committed with the rest of it, and has git merge it by
+    /// keeping the lines of both sides. Returns the path of the file and the
+    /// number of todos moved.
+    pub fn share(&mut self, dir: &str) -> Result<(PathBuf, usize)> {
+        self.check_writable()?;
+        let dir = &self.resolve(dir).to_string();
+        if let Some(path) = shared::find(dir) {
+            return Err(Error::Conflict(format!("{path:?} already exists")));
+        }
+        let list = self.storage.load(dir)?.unwrap_or_default();
+        shared::save(dir, &list)?;
+        shared::set_merge_attribute(dir)?;
+        self.storage
+            .save(dir, &TodoList::new(list.next_id, Vec::new()))?;
+        Ok((key_path(dir).join(shared::SHARED_NAME), list.records.len()))
+    }
+
+    /// Moves the todos of the `.todo.tsv` file of `dir` back into the store
+    /// and deletes the file. Returns the number of todos moved.
+    pub fn unshare(&mut self, dir: &str) -> Result<usize> {
+        self.check_writable()?;
+        let dir = &self.resolve(dir).to_string();
+        let path = shared::find(dir).ok_or_else(|| {
+            Error::NotFound(format!(
+                "\"{}\" has no {} file",
+                display_key(dir),
+                shared::SHARED_NAME
+            ))
+        })?;
+        let list = shared::load(&path)?;
+        let next_id = self.storage.load(dir)?.unwrap_or_default().next_id;
+        let records = list.records.len();
+        self.storage
+            .save(dir, &TodoList::new(next_id, list.records))?;
+        shared::remove(&path)?;
+        Ok(records)
+    }
+
+    /// The lists `relocate` would move: the one of `from` and those of the
+    /// directories below it, each paired with its new key under `to`, none
+    /// if they have no todos. Errors if a new key already has a list.
+    pub fn relocation(&mut self, from: &str, to: &str) -> Result<Vec<(String, String)>> {
+        let (from_path, to_path) = (key_path(from), key_path(to));
+        let mut moves = Vec::new();
+        for dir in self.storage.dirs()? {
+            let Ok(rest) = key_path(&dir)
+                .strip_prefix(&from_path)
+                .map(Path::to_path_buf)
+            else {
+                continue;
+            };
+            let new_key = match rest.as_os_str().is_empty() {
+                true => to.to_string(),
+                false => path_to_key(to_path.join(rest).as_os_str()),
+            };
+            if self.storage.load(&new_key)?.is_some() {
+                return Err(Error::Conflict(format!(
+                    "\"{}\" already has a todo list",
+                    display_key(&new_key)
+                )));
+            }
+            moves.push((dir, new_key));
+        }
+        Ok(moves)
+    }
+
+    /// Moves the todo list of `from`, and those of the directories below it,
+    /// to `to`, for when the directory was moved. Aliases follow the lists.
+    /// Returns the (old, new) keys of the lists moved, and fails with
+    /// `Error::NotFound` if there are none.
+    pub fn relocate(&mut self, from: &str, to: &str) -> Result<Vec<(String, String)>> {
+        self.check_writable()?;
+        let moves = self.relocation(from, to)?;
+        if moves.is_empty() {
+            return Err(Error::NotFound(format!(
+                "No Todos @ or below: \"{}\"",
+                display_key(from)
+            )));
+        }
+        for (old, new) in &moves {
+            self.storage.rename(old, new)?;
+            self.aliases.retarget(old, new);
+        }
+        self.aliases.save(&mut self.dir)?;
+        Ok(moves)
+    }
+
+    /// Lists that may have belonged to `dir` before it was moved: their
+    /// directory is gone and had the same name
+    pub fn stranded(&mut self, dir: &str) -> Result<Vec<String>> {
+        let Some(name) = key_path(dir).file_name().map(|name| name.to_os_string()) else {
+            return Ok(Vec::new());
+        };
+        Ok(self
+            .storage
+            .dirs()?
+            .into_iter()
+            .filter(|other| {
+                let path = key_path(other);
+                path.file_name() == Some(name.as_os_str()) && !path.exists()
+            })
+            .collect())
+    }
+
+    /// Lists whose directory is gone or that have nothing left to do
+    pub fn stale_lists(&mut self) -> Result<Vec<StaleList>> {
+        let mut stale = Vec::new();
+        for (dir, records) in self.list_all()? {
+            let reason = if !key_path(&dir).is_dir() {
+                StaleReason::DirGone
+            } else if shared::find(&dir).is_some() {
+                // the repository it's committed to decides when it goes
+                continue;
+            } else if records.is_empty() {
+                StaleReason::Empty
+            } else if records.iter().all(|record| !record.status.is_open()) {
+                StaleReason::AllDone
+            } else {
+                continue;
+            };
+            stale.push(StaleList {
+                dir,
+                reason,
+                records,
+            });
+        }
+        Ok(stale)
+    }
+
+    /// Drops the todo list of `dir`, moving its todos into the store's
+    /// archive first when `archive` is set. Returns the todos it had.
+    pub fn remove_list(&mut self, dir: &str, archive: bool) -> Result<Vec<Record>> {
+        self.check_writable()?;
+        let dir = &self.resolve(dir).to_string();
+        let records = self.load_existing(dir)?.records;
+        if archive {
+            let archived_at = unix_now();
+            let archived = records
+                .iter()
+                .map(|record| Archived {
+                    archived_at,
+                    dir: dir.to_string(),
+                    record: record.clone(),
+                })
+                .collect();
+            archive::append(&mut self.dir, archived)?;
+        }
+        self.storage.remove(dir)?;
+        Ok(records)
+    }
+
+    /// Checks the store in `dir` for damage, repairing what can be without
+    /// losing data when `repair` is set, and moving the rest into its
+    /// `quarantine` directory. Unlike `open` this works on stores with
+    /// malformed files.
+    pub fn fsck(
+        dir: impl Into<PathBuf>,
+        backend: Option<Backend>,
+        lock_timeout: Duration,
+        repair: bool,
+    ) -> Result<Vec<Finding>> {
+        let mut dir = dir.into();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let access = if repair { Access::Write } else { Access::Read };
+        let _lock = lock_store(&mut dir, access, lock_timeout)?;
+        let mut findings = match backend.unwrap_or_else(|| detect_backend(&mut dir)) {
+            Backend::Tsv => fsck::check_tsv(&mut dir, repair)?,
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite => crate::sqlite::integrity_check(&dir)?,
+            #[cfg(not(feature = "sqlite"))]
+            backend @ Backend::Sqlite => return Err(Error::BackendUnavailable(backend)),
+        };
+        findings.extend(fsck::check_aliases(&mut dir, repair)?);
+        Ok(findings)
+    }
+
+    /// Upgrades data written by older versions of the store, including
+    /// moving lists kept under a non-canonical directory key to the
+    /// canonical one.
+    pub fn migrate(&mut self) -> Result<Vec<Migration>> {
+        self.check_writable()?;
+        let mut done = self.storage.migrate()?;
+        let mut rekeyed = false;
+        for dir in self.storage.dirs()? {
+            let canonical = dir_key(&key_path(&dir))?;
+            if canonical == dir {
+                continue;
+            }
+            if self.storage.load(&canonical)?.is_some() {
+                done.push(Migration::Duplicate { dir, canonical });
+                continue;
+            }
+            self.storage.rename(&dir, &canonical)?;
+            self.aliases.retarget(&dir, &canonical);
+            rekeyed = true;
+            done.push(Migration::Canonicalized {
+                from: dir,
+                to: canonical,
+            });
+        }
+        if rekeyed {
+            self.aliases.save(&mut self.dir)?;
+        }
+        Ok(done)
+    }
+
+    /// Copies every list into the `to` backend, which the store uses from then
+    /// on, and moves the old backend's files aside. Returns the number of
+    /// lists copied and where the old files went.
+    pub fn convert(&mut self, to: Backend) -> Result<(usize, PathBuf)> {
+        self.check_writable()?;
+        if to == self.backend {
+            return Err(Error::Conflict(format!("the store already uses {to}")));
+        }
+        if backend_exists(&mut self.dir, to) {
+            return Err(Error::Conflict(format!(
+                "the store in {:?} already has {to} data",
+                self.dir
+            )));
+        }
+        let mut target = open_storage(&self.dir, to, Access::Write)?;
+        let dirs = self.storage.dirs()?;
+        for dir in &dirs {
+            let list = self.storage.load(dir)?.unwrap_or_default();
+            target.save(dir, &list)?;
+        }
+        let source = std::mem::replace(&mut self.storage, target);
+        self.backend = to;
+        let backup = source.retire()?;
+        Ok((dirs.len(), backup))
+    }
+
+    /// Changes `todo` in `dir` with `f`, stamping `updated_at` and
+    /// journaling the change as `op`
+    fn modify(
+        &mut self,
+        dir: &str,
+        todo: TodoRef,
+        op: Operation,
+        f: impl FnOnce(&mut Record),
+    ) -> Result<Record> {
+        self.check_writable()?;
+        let dir = &self.resolve(dir).to_string();
+        let mut list = self.load_existing(dir)?;
+        let idx = list.position(todo).ok_or_else(|| no_record(dir, todo))?;
+        let before = list.records[idx].clone();
+        let record = &mut list.records[idx];
+        f(record);
+        record.updated_at = Some(unix_now());
+        let record = record.clone();
+        self.save_list(dir, &list)?;
+        self.journal(op, dir, Some(before), Some(record.clone()))?;
+        Ok(record)
+    }
+
+    fn journal(
+        &mut self,
+        op: Operation,
+        dir: &str,
+        before: Option<Record>,
+        after: Option<Record>,
+    ) -> Result<()> {
+        let entry = JournalEntry {
+            at: unix_now(),
+            op,
+            dir: dir.to_string(),
+            before,
+            after,
+        };
+        journal::append(&mut self.dir, entry)
+    }
+
+    /// Undoes or redoes, as `op` says, the next journal entry in line
+    fn replay(&mut self, op: Operation) -> Result<JournalEntry> {
+        self.check_writable()?;
+        let mut entries = journal::load(&mut self.dir)?;
+        let (done, undone) = journal::undo_stacks(&entries);
+        let mut stack = match op {
+            Operation::Undo => done,
+            _ => undone,
+        };
+        let idx = loop {
+            let idx = stack
+                .pop()
+                .ok_or_else(|| Error::NotFound(format!("nothing to {op}")))?;
+            let entry = &entries[idx];
+            if entry.before.is_some() || entry.after.is_some() {
+                break idx;
+            }
+            // a change to a purged todo, passed over with nothing to apply
+            self.journal(op, &entry.dir, None, None)?;
+        };
+        let entry = entries.swap_remove(idx);
+        let (from, to) = match op {
+            Operation::Undo => (&entry.after, &entry.before),
+            _ => (&entry.before, &entry.after),
+        };
+        self.apply(&entry.dir, from.as_ref(), to.as_ref())?;
+        // the archive follows the todos taken out of or put back in a list
+        let archived = match entry.op {
+            Operation::Delete => entry.before.clone(),
+            Operation::Restore => entry.after.clone(),
+            _ => None,
+        };
+        if let Some(record) = archived {
+            match (entry.op, op) {
+                (Operation::Delete, Operation::Undo) | (Operation::Restore, Operation::Redo) => {
+                    // an expired one is gone already
+                    self.unarchive(&entry.dir, |other| *other == record)?;
+                }
+                _ => self.archive(&entry.dir, record)?,
+            }
+        }
+        self.journal(op, &entry.dir, from.clone(), to.clone())?;
+        Ok(entry)
+    }
+
+    fn archive(&mut self, dir: &str, record: Record) -> Result<()> {
+        let archived = Archived {
+            archived_at: unix_now(),
+            dir: dir.to_string(),
+            record,
+        };
+        archive::append(&mut self.dir, vec![archived])
+    }
+
+    /// Takes the latest archived todo of `dir` matching `f` out of the
+    /// archive, if there is one
+    fn unarchive(&mut self, dir: &str, f: impl Fn(&Record) -> bool) -> Result<Option<Record>> {
+        let mut archived = archive::load(&mut self.dir)?;
+        let Some(idx) = archived
+            .iter()
+            .rposition(|entry| entry.dir == dir && f(&entry.record))
+        else {
+            return Ok(None);
+        };
+        let entry = archived.remove(idx);
+        archive::save(&mut self.dir, &archived)?;
+        Ok(Some(entry.record))
+    }
+
+    /// Turns the todo `from` of `dir` into `to`, `None` meaning no todo.
+    /// Fails if the todo isn't `from` anymore.
+    fn apply(&mut self, dir: &str, from: Option<&Record>, to: Option<&Record>) -> Result<()> {
+        let Some(id) = from.or(to).map(|record| record.id) else {
+            return Ok(());
+        };
+        let mut list = self.load_list(dir)?.unwrap_or_default();
+        let idx = list.records.iter().position(|record| record.id == id);
+        if idx.map(|idx| &list.records[idx]) != from {
+            return Err(Error::Conflict(format!(
+                "the todo with ID {id} @ \"{}\" was changed since",
+                display_key(dir)
+            )));
+        }
+        match (idx, to) {
+            (Some(idx), Some(to)) => list.records[idx] = to.clone(),
+            (Some(idx), None) => {
+                list.records.remove(idx);
+            }
+            (None, Some(to)) => list.insert(to.clone()),
+            (None, None) => {}
+        }
+        self.save_list(dir, &list)
+    }
+
+    /// Todo list of `dir` in listing order, from its `.todo.tsv` file if it
+    /// has one
+    fn load_list(&mut self, dir: &str) -> Result<Option<TodoList>> {
+        let mut list = match shared::find(dir) {
+            Some(path) => Some(shared::load(&path)?),
+            None => self.storage.load(dir)?,
+        };
+        if let Some(list) = &mut list {
+            list.sort();
+        }
+        Ok(list)
+    }
+
+    /// Saves the todo list of `dir` where `load_list` finds it. A shared
+    /// list leaves an empty one in the storage, so listings of the whole
+    /// store include it.
+    fn save_list(&mut self, dir: &str, list: &TodoList) -> Result<()> {
+        if shared::find(dir).is_none() {
+            return self.storage.save(dir, list);
+        }
+        if self.storage.load(dir)?.is_none() {
+            self.storage.save(dir, &TodoList::default())?;
+        }
+        shared::save(dir, list)
+    }
+
+    /// Todo list of `dir`, which must have one
+    fn load_existing(&mut self, dir: &str) -> Result<TodoList> {
+        self.load_list(dir)?
+            .ok_or_else(|| Error::NotFound(format!("No Todos @ PWD: \"{}\"", display_key(dir))))
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        match self.access {
+            Access::Write => Ok(()),
+            Access::Read => Err(Error::ReadOnly),
+        }
+    }
+}
+
+/// `tags` together with those written in `text`, sorted and without repeats
+fn with_text_tags(mut tags: Vec<String>, text: &str) -> Vec<String> {
+    tags.extend(tags_in(text).map(str::to_string));
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+fn no_record(dir: &str, todo: TodoRef) -> Error {
+    Error::NotFound(format!(
+        "no record @ {todo} and path \"{}\"",
+        display_key(dir)
+    ))
+}
+
+/// SQLite if the store only has a database, else TSV
+fn detect_backend(dir: &mut PathBuf) -> Backend {
+    if !backend_exists(dir, Backend::Tsv) && backend_exists(dir, Backend::Sqlite) {
+        Backend::Sqlite
+    } else {
+        Backend::Tsv
+    }
+}
+
+pub(crate) fn backend_exists(dir: &mut PathBuf, backend: Backend) -> bool {
+    match backend {
+        Backend::Tsv => TsvStorage::exists(dir),
+        Backend::Sqlite => with_pushed(dir, SQLITE_NAME, |path| path.is_file()),
+    }
+}
+
+fn open_storage(dir: &Path, backend: Backend, access: Access) -> Result<Box<dyn Storage>> {
+    match backend {
+        Backend::Tsv => Ok(Box::new(TsvStorage::open(dir.to_path_buf())?)),
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite => Ok(Box::new(crate::sqlite::SqliteStorage::open(
+            dir,
+            access == Access::Write,
+        )?)),
+        #[cfg(not(feature = "sqlite"))]
+        Backend::Sqlite => {
+            let _ = access;
+            Err(Error::BackendUnavailable(backend))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dir_map::{DIR_MAP_NAME, todo_file_name},
+        temp_dir::TempDir,
+    };
+    use TodoRef::{Id, Position};
+
+    /// The backends this build can test
+    const BACKENDS: &[Backend] = &[
+        Backend::Tsv,
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite,
+    ];
+
+    fn open(temp: &TempDir, access: Access) -> Result<TodoStore> {
+        TodoStore::open(temp.path(), None, access, Duration::ZERO)
+    }
+
+    fn texts(records: &[Record]) -> Vec<(u64, &str, Status)> {
+        records
+            .iter()
+            .map(|r| (r.id, r.text.as_str(), r.status))
+            .collect()
+    }
+
+    #[test]
+    fn adds_todos_to_each_directory() {
+        let temp = TempDir::new("store-add");
+        let mut store = open(&temp, Access::Write).unwrap();
+        assert_eq!(store.list("/p").unwrap(), None);
+        store.add("/p", NewTodo::new("one")).unwrap();
+        store.add("/q", NewTodo::new("elsewhere")).unwrap();
+        assert_eq!(store.add("/p", NewTodo::new("two")).unwrap().id, 1);
+        drop(store);
+
+        let mut store = open(&temp, Access::Read).unwrap();
+        let p = store.list("/p").unwrap().unwrap();
+        assert_eq!(
+            texts(&p),
+            [(0, "one", Status::Active), (1, "two", Status::Active)]
+        );
+        let all = store.list_all().unwrap();
+        let dirs: Vec<&str> = all.iter().map(|(dir, _)| dir.as_str()).collect();
+        assert_eq!(dirs, ["/p", "/q"]);
+        assert!(temp.path().join(todo_file_name("/q")).is_file());
+    }
+
+    #[test]
+    fn add_rejects_duplicates() {
+        let temp = TempDir::new("store-add-reject");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("one")).unwrap();
+        assert!(matches!(
+            store.add("/p", NewTodo::new("one")),
+            Err(Error::AlreadyExists { id: 0, .. })
+        ));
+        store.add("/q", NewTodo::new("one")).unwrap();
+        assert_eq!(store.list("/p").unwrap().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn text_keeps_tabs_newlines_and_backslashes() {
+        let text = "line one\n\tindented \\n not a newline\r\n";
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-escape-{backend}"));
+            let open =
+                || TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO);
+            let mut store = open().unwrap();
+            store.add("/p", NewTodo::new(text)).unwrap();
+            store.add("/p", NewTodo::new("tab\tin the middle")).unwrap();
+            store.update("/p", Id(1), "tab\t\tdoubled").unwrap();
+            drop(store);
+            let p = open().unwrap().list("/p").unwrap().unwrap();
+            assert_eq!(
+                texts(&p),
+                [
+                    (0, text, Status::Active),
+                    (1, "tab\t\tdoubled", Status::Active)
+                ],
+                "{backend}"
+            );
+        }
+    }
+
+    #[test]
+    fn updates_text_and_status() {
+        let temp = TempDir::new("store-modify");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("one")).unwrap();
+        store.update("/p", Id(0), "first").unwrap();
+        store.set_status("/p", Id(0), Status::Done, None).unwrap();
+        let p = store.list("/p").unwrap().unwrap();
+        assert_eq!(texts(&p), [(0, "first", Status::Done)]);
+        assert!(matches!(
+            store.update("/p", Id(1), "x"),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            store.set_status("/q", Id(0), Status::Done, None),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn delete_confirms_active_todos() {
+        let temp = TempDir::new("store-delete");
+        let mut store = open(&temp, Access::Write).unwrap();
+        for text in ["one", "two", "three"] {
+            store.add("/p", NewTodo::new(text)).unwrap();
+        }
+        assert!(matches!(
+            store.delete("/p", Id(0), true, |_| false),
+            Err(Error::Cancelled)
+        ));
+        assert_eq!(store.list("/p").unwrap().unwrap().len(), 3);
+        assert_eq!(
+            store.delete("/p", Id(0), true, |_| true).unwrap().text,
+            "one"
+        );
+        store.set_status("/p", Id(2), Status::Done, None).unwrap();
+        store
+            .delete("/p", Id(2), true, |_| {
+                panic!("done todos are deleted without asking")
+            })
+            .unwrap();
+        let p = store.list("/p").unwrap().unwrap();
+        assert_eq!(texts(&p), [(1, "two", Status::Active)]);
+    }
+
+    #[test]
+    fn migrate_renames_old_files() {
+        let temp = TempDir::new("store-migrate");
+        let dir = temp.path();
+        std::fs::write(dir.join("1234.tsv"), "0\told\t[ ]\n").unwrap();
+        std::fs::write(dir.join("5678.tsv"), "0\tkept\t[ ]\n").unwrap();
+        // the new name of /b is taken, so /b keeps its old one
+        std::fs::write(dir.join(todo_file_name("/b")), "").unwrap();
+        std::fs::write(dir.join(DIR_MAP_NAME), "/a\t1234.tsv\n/b\t5678.tsv\n").unwrap();
+
+        let mut store = open(&temp, Access::Write).unwrap();
+        let done = store.migrate().unwrap();
+        assert!(matches!(&done[..], [
+            Migration::BackedUp(_),
+            Migration::Renamed { dir: a, .. },
+            Migration::Conflict { dir: b, .. },
+            ..
+        ] if a == "/a" && b == "/b"));
+        assert!(!dir.join("1234.tsv").exists());
+        assert_eq!(store.list("/a").unwrap().unwrap()[0].text, "old");
+        assert_eq!(store.list("/b").unwrap().unwrap()[0].text, "kept");
+        drop(store);
+        let dir_map = std::fs::read_to_string(dir.join(DIR_MAP_NAME)).unwrap();
+        assert_eq!(
+            dir_map,
+            format!("#format\t2\n/a\t{}\n/b\t5678.tsv\n", todo_file_name("/a"))
+        );
+    }
+
+    #[test]
+    fn readers_share_the_lock_and_writers_wait() {
+        let temp = TempDir::new("store-lock");
+        let reader = open(&temp, Access::Read).unwrap();
+        let other_reader = open(&temp, Access::Read).unwrap();
+        let err = open(&temp, Access::Write).err().unwrap();
+        assert!(matches!(err, Error::LockTimeout { .. }), "{err}");
+        assert_eq!(err.exit_code(), 6);
+
+        let dir = temp.path().to_path_buf();
+        let writer = std::thread::spawn(move || {
+            TodoStore::open(dir, None, Access::Write, Duration::from_secs(10)).is_ok()
+        });
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!writer.is_finished());
+        drop((reader, other_reader));
+        assert!(writer.join().unwrap());
+    }
+
+    #[test]
+    fn backends_keep_the_same_lists() {
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-backend-{backend}"));
+            let open =
+                || TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO);
+            let mut store = open().unwrap();
+            for text in ["one", "two"] {
+                store.add("/p", NewTodo::new(text)).unwrap();
+            }
+            store.add("/q", NewTodo::new("three")).unwrap();
+            store.update("/p", Id(1), "second").unwrap();
+            store.set_status("/q", Id(0), Status::Done, None).unwrap();
+            store.delete("/p", Id(0), true, |_| true).unwrap();
+            drop(store);
+
+            let mut store = open().unwrap();
+            assert_eq!(store.backend(), backend);
+            let all = store.list_all().unwrap();
+            assert_eq!(all.len(), 2, "{backend}");
+            assert_eq!(all[0].0, "/p");
+            assert_eq!(
+                texts(&all[0].1),
+                [(1, "second", Status::Active)],
+                "{backend}"
+            );
+            assert_eq!(texts(&all[1].1), [(0, "three", Status::Done)], "{backend}");
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn convert_copies_every_list() {
+        let temp = TempDir::new("store-convert");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("one")).unwrap();
+        store.add("/q", NewTodo::new("two")).unwrap();
+        assert!(matches!(
+            store.convert(Backend::Tsv),
+            Err(Error::Conflict(_))
+        ));
+        let (copied, backup) = store.convert(Backend::Sqlite).unwrap();
+        assert_eq!(copied, 2);
+        assert!(backup.exists());
+        drop(store);
+
+        // the store is detected as SQLite from now on
+        let mut store = open(&temp, Access::Write).unwrap();
+        assert_eq!(store.backend(), Backend::Sqlite);
+        assert_eq!(store.list("/q").unwrap().unwrap()[0].text, "two");
+        store.add("/p", NewTodo::new("three")).unwrap();
+        store.convert(Backend::Tsv).unwrap();
+        assert_eq!(store.list("/p").unwrap().unwrap().len(), 2);
+
+        // converting again keeps the backups of the earlier conversions
+        let (_, second) = store.convert(Backend::Sqlite).unwrap();
+        assert_ne!(second, backup);
+        assert!(backup.exists() && second.exists());
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    #[test]
+    fn sqlite_needs_the_feature() {
+        let temp = TempDir::new("store-no-sqlite");
+        let err = TodoStore::open(
+            temp.path(),
+            Some(Backend::Sqlite),
+            Access::Write,
+            Duration::ZERO,
+        );
+        assert!(matches!(
+            err,
+            Err(Error::BackendUnavailable(Backend::Sqlite))
+        ));
+    }
+
+    #[test]
+    fn ids_are_never_reused() {
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-ids-{backend}"));
+            let open =
+                || TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO);
+            let mut store = open().unwrap();
+            for text in ["one", "two", "three"] {
+                store.add("/p", NewTodo::new(text)).unwrap();
+            }
+            store.delete("/p", Id(2), true, |_| true).unwrap();
+            store.delete("/p", Id(0), true, |_| true).unwrap();
+            drop(store);
+
+            let mut store = open().unwrap();
+            assert_eq!(
+                store.add("/p", NewTodo::new("four")).unwrap().id,
+                3,
+                "{backend}"
+            );
+            let p = store.list("/p").unwrap().unwrap();
+            assert_eq!(
+                texts(&p),
+                [(1, "two", Status::Active), (3, "four", Status::Active)]
+            );
+        }
+    }
+
+    #[test]
+    fn positions_count_from_one_in_listing_order() {
+        let temp = TempDir::new("store-positions");
+        let mut store = open(&temp, Access::Write).unwrap();
+        for text in ["one", "two", "three"] {
+            store.add("/p", NewTodo::new(text)).unwrap();
+        }
+        store.delete("/p", Position(1), true, |_| true).unwrap();
+        assert_eq!(store.update("/p", Position(2), "3").unwrap().id, 2);
+        assert_eq!(
+            store
+                .set_status("/p", Position(1), Status::Done, None)
+                .unwrap()
+                .text,
+            "two"
+        );
+        assert!(matches!(
+            store.update("/p", Position(3), "x"),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn aliases_share_the_list_of_their_dir() {
+        let temp = TempDir::new("store-alias");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("one")).unwrap();
+        store.add_alias("/mnt/p", "/p").unwrap();
+        store.add("/mnt/p", NewTodo::new("two")).unwrap();
+        store.update("/mnt/p", Id(0), "first").unwrap();
+        // an alias of an alias points at the dir with the list
+        store.add_alias("/mnt2/p", "/mnt/p").unwrap();
+        assert_eq!(store.resolve("/mnt2/p"), "/p");
+        drop(store);
+
+        let mut store = open(&temp, Access::Write).unwrap();
+        let p = store.list("/mnt2/p").unwrap().unwrap();
+        assert_eq!(
+            texts(&p),
+            [(0, "first", Status::Active), (1, "two", Status::Active)]
+        );
+        assert_eq!(store.list_all().unwrap().len(), 1);
+        assert_eq!(store.remove_alias("/mnt/p").unwrap(), "/p");
+        assert_eq!(store.list("/mnt/p").unwrap(), None);
+        assert!(matches!(
+            store.remove_alias("/mnt/p"),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn aliases_cant_hide_a_list() {
+        let temp = TempDir::new("store-alias-reject");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("one")).unwrap();
+        store.add("/q", NewTodo::new("two")).unwrap();
+        for (alias, dir) in [("/q", "/p"), ("/p", "/p")] {
+            assert!(matches!(
+                store.add_alias(alias, dir),
+                Err(Error::Conflict(_))
+            ));
+        }
+        assert_eq!(store.aliases().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn migrate_moves_lists_to_canonical_keys() {
+        let temp = TempDir::new("store-canonical");
+        let root = std::fs::canonicalize(temp.path()).unwrap();
+        let real = root.join("real");
+        std::fs::create_dir(&real).unwrap();
+        std::os::unix::fs::symlink(&real, root.join("link")).unwrap();
+        let link_key = root.join("link").to_str().unwrap().to_string();
+        let real_key = real.to_str().unwrap().to_string();
+
+        let store_dir = TempDir::new("store-canonical-store");
+        let mut store = open(&store_dir, Access::Write).unwrap();
+        store.add(&link_key, NewTodo::new("one")).unwrap();
+        store.add_alias("/elsewhere", &link_key).unwrap();
+        let done = store.migrate().unwrap();
+        assert_eq!(
+            done,
+            [Migration::Canonicalized {
+                from: link_key.clone(),
+                to: real_key.clone(),
+            }]
+        );
+        assert_eq!(store.resolve("/elsewhere"), real_key);
+        assert_eq!(store.list(&link_key).unwrap(), None);
+        assert_eq!(store.list(&real_key).unwrap().unwrap()[0].text, "one");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn any_directory_name_has_a_list() {
+        use std::os::unix::ffi::OsStrExt;
+        let temp = TempDir::new("store-odd-names");
+        let odd = [
+            crate::key::dir_key(Path::new("/tmp/tab\there")).unwrap(),
+            crate::key::dir_key(Path::new(std::ffi::OsStr::from_bytes(b"/tmp/caf\xe9"))).unwrap(),
+        ];
+        for &backend in BACKENDS {
+            let store_dir = temp.path().join(backend.name());
+            let open = || TodoStore::open(&store_dir, Some(backend), Access::Write, Duration::ZERO);
+            let mut store = open().unwrap();
+            for dir in &odd {
+                store.add(dir, NewTodo::new("odd")).unwrap();
+            }
+            drop(store);
+            let mut store = open().unwrap();
+            let dirs: Vec<String> = store
+                .list_all()
+                .unwrap()
+                .into_iter()
+                .map(|(d, _)| d)
+                .collect();
+            assert_eq!(dirs, odd, "{backend}");
+            assert_eq!(key_path(&odd[0]), Path::new("/tmp/tab\there"));
+        }
+    }
+
+    #[test]
+    fn migrate_upgrades_old_formats_after_a_backup() {
+        let temp = TempDir::new("store-upgrade");
+        let dir = temp.path();
+        let name = todo_file_name("/a");
+        let old_list = "0\tfirst\t[ ]\n2\ta \\ path\t[✓]\n";
+        let old_dir_map = format!("/a\t{name}\n");
+        std::fs::write(dir.join(&name), old_list).unwrap();
+        std::fs::write(dir.join(DIR_MAP_NAME), &old_dir_map).unwrap();
+
+        let mut store = open(&temp, Access::Write).unwrap();
+        let before = store.list("/a").unwrap();
+        let done = store.migrate().unwrap();
+        let [
+            Migration::BackedUp(backup),
+            Migration::Upgraded { from: 1, .. },
+            Migration::Upgraded { from: 1, to: 2, .. },
+        ] = &done[..]
+        else {
+            panic!("{done:?}");
+        };
+        assert_eq!(
+            std::fs::read_to_string(backup.join(&name)).unwrap(),
+            old_list
+        );
+        let backed_up_map = std::fs::read_to_string(backup.join(DIR_MAP_NAME)).unwrap();
+        assert_eq!(backed_up_map, old_dir_map);
+        assert_eq!(store.list("/a").unwrap(), before);
+        let upgraded = std::fs::read_to_string(dir.join(&name)).unwrap();
+        let header = format!("#format\t{}\n", crate::record::FORMAT_VERSION);
+        assert!(upgraded.starts_with(&header), "{upgraded}");
+        // nothing left to upgrade, so no second backup
+        assert_eq!(store.migrate().unwrap(), []);
+    }
+
+    #[test]
+    fn newer_formats_are_refused() {
+        let temp = TempDir::new("store-newer");
+        std::fs::write(temp.path().join(DIR_MAP_NAME), "#format\t99\n").unwrap();
+        let err = open(&temp, Access::Read).err().unwrap();
+        assert!(
+            matches!(err, Error::UnsupportedVersion { version: 99, .. }),
+            "{err}"
+        );
+        assert_eq!(err.exit_code(), 14);
+    }
+
+    /// A TSV store with one of each kind of damage fsck knows about
+    fn damaged_store(temp: &TempDir) {
+        let dir = temp.path();
+        let (a, b) = (todo_file_name("/a"), todo_file_name("/b"));
+        let dir_map =
+            format!("#format\t2\n/a\t{a}\nnot an entry\n/b\t{b}\n/a\t{b}\n/gone\tgone.tsv\n");
+        std::fs::write(dir.join(DIR_MAP_NAME), dir_map).unwrap();
+        std::fs::write(
+            dir.join(&a),
+            "#format\t3\n0\tzero\t[ ]\n0\tagain\t[ ]\nbroken\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join(&b), "#format\t3\n0\tb\t[ ]\n").unwrap();
+        std::fs::write(dir.join("0123abcd.tsv"), "0\torphan\t[ ]\n").unwrap();
+        std::fs::write(dir.join(crate::alias::ALIASES_NAME), "/x\t/a\nno tab\n").unwrap();
+    }
+
+    fn problems(findings: &[Finding]) -> Vec<&crate::Problem> {
+        findings.iter().map(|finding| &finding.problem).collect()
+    }
+
+    #[test]
+    fn fsck_reports_damage_without_touching_it() {
+        use crate::Problem::*;
+        let temp = TempDir::new("store-fsck");
+        damaged_store(&temp);
+        let before = std::fs::read_to_string(temp.path().join(DIR_MAP_NAME)).unwrap();
+        let findings = TodoStore::fsck(temp.path(), None, Duration::ZERO, false).unwrap();
+        let found = problems(&findings);
+        assert!(
+            matches!(
+                found[..],
+                [
+                    MalformedLine { line: 3, .. },
+                    DuplicateKey { .. },
+                    MissingFile { .. },
+                    MalformedLine { line: 4, .. },
+                    DuplicateId { id: 0, .. },
+                    OrphanFile(_),
+                    MalformedLine { line: 2, .. },
+                ]
+            ),
+            "{found:?}"
+        );
+        assert!(findings.iter().all(|finding| finding.repair.is_none()));
+        let after = std::fs::read_to_string(temp.path().join(DIR_MAP_NAME)).unwrap();
+        assert_eq!(before, after);
+        assert!(open(&temp, Access::Read).is_err());
+    }
+
+    #[test]
+    fn fsck_repair_keeps_every_todo() {
+        let temp = TempDir::new("store-fsck-repair");
+        damaged_store(&temp);
+        let findings = TodoStore::fsck(temp.path(), None, Duration::ZERO, true).unwrap();
+        assert_eq!(findings.len(), 7);
+        assert!(findings.iter().all(|finding| finding.repair.is_some()));
+        assert_eq!(
+            TodoStore::fsck(temp.path(), None, Duration::ZERO, false).unwrap(),
+            []
+        );
+
+        let mut store = open(&temp, Access::Read).unwrap();
+        let a = store.list("/a").unwrap().unwrap();
+        assert_eq!(
+            texts(&a),
+            [(0, "zero", Status::Active), (1, "again", Status::Active)]
+        );
+        assert_eq!(store.list("/b").unwrap().unwrap()[0].text, "b");
+        assert_eq!(store.resolve("/x"), "/a");
+        let quarantine = temp.path().join("quarantine");
+        let lines = std::fs::read_to_string(quarantine.join(todo_file_name("/a"))).unwrap();
+        assert_eq!(lines, "broken\n");
+        assert!(quarantine.join("0123abcd.tsv").is_file());
+    }
+
+    #[test]
+    fn fsck_of_a_missing_store_finds_nothing() {
+        let temp = TempDir::new("store-fsck-missing");
+        let missing = temp.path().join("missing");
+        assert_eq!(
+            TodoStore::fsck(&missing, None, Duration::ZERO, true).unwrap(),
+            []
+        );
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn stale_lists_are_gone_empty_or_done() {
+        let temp = TempDir::new("store-stale");
+        let root = std::fs::canonicalize(temp.path()).unwrap();
+        let key = |name: &str| {
+            let dir = root.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            dir.to_str().unwrap().to_string()
+        };
+        let (busy, empty, done) = (key("busy"), key("empty"), key("done"));
+        let gone = root.join("gone").to_str().unwrap().to_string();
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add(&busy, NewTodo::new("open")).unwrap();
+        store.add(&busy, NewTodo::new("closed")).unwrap();
+        store.set_status(&busy, Id(1), Status::Done, None).unwrap();
+        store.add(&empty, NewTodo::new("deleted")).unwrap();
+        store.delete(&empty, Id(0), true, |_| true).unwrap();
+        store.add(&done, NewTodo::new("closed")).unwrap();
+        store.set_status(&done, Id(0), Status::Done, None).unwrap();
+        store.add(&gone, NewTodo::new("open")).unwrap();
+
+        let stale: Vec<(String, StaleReason, usize)> = store
+            .stale_lists()
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.dir, s.reason, s.records.len()))
+            .collect();
+        assert_eq!(
+            stale,
+            [
+                (empty, StaleReason::Empty, 0),
+                (done, StaleReason::AllDone, 1),
+                (gone, StaleReason::DirGone, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn removed_lists_can_be_archived() {
+        let temp = TempDir::new("store-remove-list");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("one")).unwrap();
+        store.add("/p", NewTodo::new("two")).unwrap();
+        store.add("/q", NewTodo::new("three")).unwrap();
+        store.add_alias("/mnt/p", "/p").unwrap();
+
+        let removed = store.remove_list("/mnt/p", true).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(store.remove_list("/q", false).unwrap().len(), 1);
+        assert!(matches!(
+            store.remove_list("/q", true),
+            Err(Error::NotFound(_))
+        ));
+        assert!(store.list_all().unwrap().is_empty());
+        let archived = archive::load(&mut store.dir).unwrap();
+        let archived: Vec<(&str, &str)> = archived
+            .iter()
+            .map(|a| (a.dir.as_str(), a.record.text.as_str()))
+            .collect();
+        assert_eq!(archived, [("/p", "one"), ("/p", "two")]);
+    }
+
+    #[test]
+    fn relocate_moves_lists_below_the_directory() {
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-relocate-{backend}"));
+            let open =
+                || TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO);
+            let mut store = open().unwrap();
+            store.add("/old", NewTodo::new("top")).unwrap();
+            store.add("/old/sub", NewTodo::new("below")).unwrap();
+            store.add("/older", NewTodo::new("not below")).unwrap();
+            store.add_alias("/mnt/sub", "/old/sub").unwrap();
+
+            let moves = store.relocate("/old", "/new").unwrap();
+            let expected = [("/old", "/new"), ("/old/sub", "/new/sub")]
+                .map(|(from, to)| (from.to_string(), to.to_string()));
+            assert_eq!(moves, expected, "{backend}");
+            drop(store);
+
+            let mut store = open().unwrap();
+            assert_eq!(store.list("/old").unwrap(), None);
+            assert_eq!(store.list("/new").unwrap().unwrap()[0].text, "top");
+            assert_eq!(store.list("/mnt/sub").unwrap().unwrap()[0].text, "below");
+            assert_eq!(store.list("/older").unwrap().unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn relocate_wont_merge_lists() {
+        let temp = TempDir::new("store-relocate-conflict");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/old", NewTodo::new("one")).unwrap();
+        store.add("/old/sub", NewTodo::new("two")).unwrap();
+        store.add("/new/sub", NewTodo::new("taken")).unwrap();
+        assert!(matches!(
+            store.relocate("/old", "/new"),
+            Err(Error::Conflict(_))
+        ));
+        // mv-dir moves directories without todos all the same
+        assert_eq!(store.relocation("/nothing", "/new").unwrap(), []);
+        assert!(matches!(
+            store.relocate("/nothing", "/new"),
+            Err(Error::NotFound(_))
+        ));
+        // nothing moved
+        assert_eq!(store.list("/old/sub").unwrap().unwrap()[0].text, "two");
+        assert_eq!(store.list("/new").unwrap(), None);
+    }
+
+    #[test]
+    fn stranded_lists_share_the_name_of_a_gone_dir() {
+        let temp = TempDir::new("store-stranded");
+        let root = std::fs::canonicalize(temp.path()).unwrap();
+        let key = |path: &Path| path.to_str().unwrap().to_string();
+        let moved = root.join("new").join("project");
+        std::fs::create_dir_all(&moved).unwrap();
+        std::fs::create_dir(root.join("other")).unwrap();
+        let gone = key(&root.join("old").join("project"));
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add(&gone, NewTodo::new("left behind")).unwrap();
+        store
+            .add(&key(&root.join("other")), NewTodo::new("unrelated"))
+            .unwrap();
+        assert_eq!(store.stranded(&key(&moved)).unwrap(), [gone]);
+        assert_eq!(
+            store.stranded(&key(&root.join("other"))).unwrap(),
+            [] as [String; 0]
+        );
+    }
+
+    #[test]
+    fn list_tree_adds_parents_and_children() {
+        let temp = TempDir::new("store-tree");
+        let mut store = open(&temp, Access::Write).unwrap();
+        for dir in ["/a/b/c", "/a", "/a/b/c/d", "/a/bc", "/x", "/a/b"] {
+            store.add(dir, NewTodo::new(dir)).unwrap();
+        }
+        let mut tree = |up, down| -> Vec<String> {
+            let lists = store.list_tree("/a/b", up, down).unwrap();
+            lists.into_iter().map(|(dir, _)| dir).collect()
+        };
+        assert_eq!(tree(false, false), ["/a/b"]);
+        assert_eq!(tree(true, false), ["/a", "/a/b"]);
+        assert_eq!(tree(false, true), ["/a/b", "/a/b/c", "/a/b/c/d"]);
+        assert_eq!(tree(true, true), ["/a", "/a/b", "/a/b/c", "/a/b/c/d"]);
+    }
+
+    #[test]
+    fn config_is_kept_with_the_store() {
+        let temp = TempDir::new("store-config");
+        let mut store = open(&temp, Access::Write).unwrap();
+        assert!(!store.config().list_up);
+        store.set_config("list.up", "true").unwrap();
+        for (key, value) in [("list.up", "yes"), ("list.down", "true")] {
+            assert!(matches!(
+                store.set_config(key, value),
+                Err(Error::InvalidConfig(_))
+            ));
+        }
+        drop(store);
+        let store = open(&temp, Access::Read).unwrap();
+        assert!(store.config().list_up);
+        assert_eq!(store.config().get("list.up").as_deref(), Some("true"));
+    }
+
+    #[test]
+    fn todos_keep_their_branch() {
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-branch-{backend}"));
+            let open =
+                || TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO);
+            let mut store = open().unwrap();
+            let on_branch = NewTodo {
+                branch: Some("feature/x".to_string()),
+                ..NewTodo::new("on a branch")
+            };
+            store.add("/p", on_branch).unwrap();
+            store.add("/p", NewTodo::new("outside git")).unwrap();
+            drop(store);
+            let p = open().unwrap().list("/p").unwrap().unwrap();
+            let branches: Vec<Option<&str>> = p.iter().map(|r| r.branch.as_deref()).collect();
+            assert_eq!(branches, [Some("feature/x"), None], "{backend}");
+        }
+    }
+
+    #[test]
+    fn shared_lists_live_in_the_directory() {
+        let temp = TempDir::new("store-share");
+        let project = std::fs::canonicalize(temp.path()).unwrap().join("project");
+        std::fs::create_dir(&project).unwrap();
+        std::fs::write(project.join(".gitattributes"), "*.png binary").unwrap();
+        let dir = project.to_str().unwrap();
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add(dir, NewTodo::new("first")).unwrap();
+        store.add(dir, NewTodo::new("second")).unwrap();
+
+        let (path, moved) = store.share(dir).unwrap();
+        assert_eq!(
+            (path.as_path(), moved),
+            (project.join(".todo.tsv").as_path(), 2)
+        );
+        assert!(store.share(dir).is_err());
+        let attributes = std::fs::read_to_string(project.join(".gitattributes")).unwrap();
+        assert_eq!(attributes, "*.png binary\n.todo.tsv merge=union\n");
+
+        let added = store.add(dir, NewTodo::new("on a branch")).unwrap();
+        assert!(shared::SHARED_IDS.contains(&added.id), "{}", added.id);
+        let raw = std::fs::read_to_string(&path).unwrap();
+        assert!(!raw.contains("#next_id"), "{raw}");
+        assert_eq!(store.list(dir).unwrap().unwrap().len(), 3);
+        assert_eq!(store.stale_lists().unwrap(), []);
+
+        assert_eq!(store.unshare(dir).unwrap(), 3);
+        assert!(!path.exists());
+        assert!(store.unshare(dir).is_err());
+        let ids: Vec<u64> = store
+            .list(dir)
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, [0, 1, added.id]);
+        assert_eq!(
+            store.add(dir, NewTodo::new("after")).unwrap().id,
+            added.id + 1
+        );
+    }
+
+    #[test]
+    fn undo_and_redo_replay_the_journal() {
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-undo-{backend}"));
+            let mut store =
+                TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO).unwrap();
+            store.add("/p", NewTodo::new("write")).unwrap();
+            store.add("/p", NewTodo::new("test")).unwrap();
+            store.update("/p", Id(0), "write more").unwrap();
+            store.delete("/p", Id(1), true, |_| true).unwrap();
+            let ops = |store: &mut TodoStore| -> Vec<Operation> {
+                store.history().unwrap().iter().map(|e| e.op).collect()
+            };
+            assert_eq!(
+                ops(&mut store),
+                [
+                    Operation::New,
+                    Operation::New,
+                    Operation::Update,
+                    Operation::Delete
+                ],
+                "{backend}"
+            );
+
+            assert_eq!(store.undo().unwrap().op, Operation::Delete);
+            assert_eq!(store.undo().unwrap().op, Operation::Update);
+            assert_eq!(
+                texts(&store.list("/p").unwrap().unwrap()),
+                [(0, "write", Status::Active), (1, "test", Status::Active)],
+                "{backend}"
+            );
+            assert_eq!(store.redo().unwrap().op, Operation::Update);
+            assert_eq!(
+                texts(&store.list("/p").unwrap().unwrap()),
+                [
+                    (0, "write more", Status::Active),
+                    (1, "test", Status::Active)
+                ],
+                "{backend}"
+            );
+
+            // a new change makes the undone delete final
+            store.set_status("/p", Id(1), Status::Done, None).unwrap();
+            assert!(matches!(store.redo(), Err(Error::NotFound(_))));
+            assert_eq!(store.undo().unwrap().op, Operation::Status);
+        }
+    }
+
+    #[test]
+    fn undo_refuses_todos_changed_since() {
+        let temp = TempDir::new("store-undo-conflict");
+        let mut store = open(&temp, Access::Write).unwrap();
+        assert!(matches!(store.undo(), Err(Error::NotFound(_))));
+        store.add("/p", NewTodo::new("write")).unwrap();
+        store.update("/p", Id(0), "write more").unwrap();
+        store.undo().unwrap();
+        // edit the list behind the journal's back
+        let list = TodoList::new(
+            1,
+            vec![Record {
+                text: "rewritten".to_string(),
+                ..store.list("/p").unwrap().unwrap()[0].clone()
+            }],
+        );
+        store.storage.save("/p", &list).unwrap();
+        assert!(matches!(store.undo(), Err(Error::Conflict(_))));
+        assert!(matches!(store.redo(), Err(Error::Conflict(_))));
+        assert_eq!(
+            texts(&store.list("/p").unwrap().unwrap()),
+            [(0, "rewritten", Status::Active)]
+        );
+    }
+
+    #[test]
+    fn deleted_todos_are_archived_until_restored() {
+        let temp = TempDir::new("store-restore");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("keep")).unwrap();
+        store.add("/p", NewTodo::new("archive")).unwrap();
+        store.add("/p", NewTodo::new("purge")).unwrap();
+        store.delete("/p", Id(1), true, |_| true).unwrap();
+        store.delete("/p", Id(2), false, |_| true).unwrap();
+        let archived = store.archived().unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!((archived[0].dir.as_str(), archived[0].record.id), ("/p", 1));
+
+        assert!(matches!(store.restore("/p", 2), Err(Error::NotFound(_))));
+        assert_eq!(store.restore("/p", 1).unwrap().text, "archive");
+        assert_eq!(store.archived().unwrap(), []);
+        assert_eq!(
+            texts(&store.list("/p").unwrap().unwrap()),
+            [(0, "keep", Status::Active), (1, "archive", Status::Active)]
+        );
+
+        // undoing the restore and the delete moves the todo back and forth
+        store.undo().unwrap();
+        assert_eq!(store.archived().unwrap().len(), 1);
+        store.redo().unwrap();
+        assert_eq!(store.archived().unwrap(), []);
+        store.delete("/p", Id(1), true, |_| true).unwrap();
+        store.undo().unwrap();
+        assert_eq!(store.archived().unwrap(), []);
+        assert_eq!(store.list("/p").unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn old_archived_todos_expire() {
+        let temp = TempDir::new("store-expire");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("new")).unwrap();
+        store.delete("/p", Id(0), true, |_| true).unwrap();
+        let old = Archived {
+            archived_at: unix_now() - 3600,
+            ..store.archived().unwrap()[0].clone()
+        };
+        archive::append(&mut store.dir, vec![old.clone()]).unwrap();
+        assert_eq!(store.expire_archive(7200).unwrap(), []);
+        assert_eq!(store.expire_archive(60).unwrap(), [old]);
+        assert_eq!(store.archived().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn priorities_reorder_the_list() {
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-priority-{backend}"));
+            let open =
+                || TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO);
+            let mut store = open().unwrap();
+            store.add("/p", NewTodo::new("first")).unwrap();
+            let urgent = NewTodo {
+                priority: Some("A".parse().unwrap()),
+                ..NewTodo::new("urgent")
+            };
+            store.add("/p", urgent).unwrap();
+            store.add("/p", NewTodo::new("last")).unwrap();
+            store
+                .set_priority("/p", Position(3), Some("B".parse().unwrap()))
+                .unwrap();
+            drop(store);
+
+            let mut store = open().unwrap();
+            let priorities = |store: &mut TodoStore| -> Vec<(u64, Option<char>)> {
+                let list = store.list("/p").unwrap().unwrap();
+                list.iter()
+                    .map(|r| (r.id, r.priority.map(|p| p.letter())))
+                    .collect()
+            };
+            assert_eq!(
+                priorities(&mut store),
+                [(1, Some('A')), (2, Some('B')), (0, None)],
+                "{backend}"
+            );
+            store.set_priority("/p", Id(1), None).unwrap();
+            let cleared = priorities(&mut store);
+            assert_eq!(cleared[0], (2, Some('B')), "{backend}");
+            assert!(cleared[1..].contains(&(1, None)), "{backend}");
+        }
+    }
+
+    #[test]
+    fn due_lists_active_todos_soonest_first() {
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-due-{backend}"));
+            let mut store =
+                TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO).unwrap();
+            let date = |s: &str| s.parse::<Date>().unwrap();
+            let due = |text: &str, due: &str| NewTodo {
+                due: Some(date(due)),
+                ..NewTodo::new(text)
+            };
+            store.add("/a", due("later", "2026-12-01")).unwrap();
+            store.add("/a", due("done", "2026-10-01")).unwrap();
+            store.set_status("/a", Id(1), Status::Done, None).unwrap();
+            store.add("/b", due("soon", "2026-10-20")).unwrap();
+            store.add("/b", NewTodo::new("whenever")).unwrap();
+            store.add("/b", NewTodo::new("moved")).unwrap();
+            store
+                .set_due("/b", Id(2), Some(date("2026-11-01")))
+                .unwrap();
+
+            let until = |store: &mut TodoStore, until: &str| -> Vec<(String, String)> {
+                let due = store.due_until(date(until)).unwrap();
+                due.into_iter().map(|(dir, r)| (dir, r.text)).collect()
+            };
+            let pair = |dir: &str, text: &str| (dir.to_string(), text.to_string());
+            assert_eq!(
+                until(&mut store, "2026-11-01"),
+                [pair("/b", "soon"), pair("/b", "moved")],
+                "{backend}"
+            );
+            store.set_due("/b", Id(2), None).unwrap();
+            assert_eq!(
+                until(&mut store, "2027-01-01"),
+                [pair("/b", "soon"), pair("/a", "later")],
+                "{backend}"
+            );
+        }
+    }
+
+    #[test]
+    fn tags_follow_the_text() {
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-tags-{backend}"));
+            let open =
+                || TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO);
+            let mut store = open().unwrap();
+            let tagged = NewTodo {
+                tags: vec!["+release".to_string()],
+                ..NewTodo::new("fix +bug @home +bug")
+            };
+            let added = store.add("/p", tagged).unwrap();
+            assert_eq!(added.tags, ["+bug", "+release", "@home"], "{backend}");
+            store.update("/p", Id(0), "fix +crash @work").unwrap();
+            drop(store);
+            let list = open().unwrap().list("/p").unwrap().unwrap();
+            assert_eq!(list[0].tags, ["+crash", "+release", "@work"], "{backend}");
+        }
+    }
+
+    #[test]
+    fn only_held_up_todos_have_a_reason() {
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-reason-{backend}"));
+            let open =
+                || TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO);
+            let mut store = open().unwrap();
+            store.add("/p", NewTodo::new("ship")).unwrap();
+            let reason = || Some("waiting\tfor CI".to_string());
+            assert!(matches!(
+                store.set_status("/p", Id(0), Status::Done, reason()),
+                Err(Error::Conflict(_))
+            ));
+            store
+                .set_status("/p", Id(0), Status::Waiting, reason())
+                .unwrap();
+            drop(store);
+
+            let mut store = open().unwrap();
+            let list = store.list("/p").unwrap().unwrap();
+            assert_eq!(list[0].status, Status::Waiting, "{backend}");
+            assert_eq!(list[0].reason, reason(), "{backend}");
+            let done = store.set_status("/p", Id(0), Status::Cancelled, None);
+            assert_eq!(done.unwrap().reason, None, "{backend}");
+            // cancelled todos are finished, like done ones
+            assert!(store.delete("/p", Id(0), true, |_| panic!("asked")).is_ok());
+        }
+    }
+
+    #[test]
+    fn changes_are_stamped() {
+        for &backend in BACKENDS {
+            let temp = TempDir::new(&format!("store-stamps-{backend}"));
+            let open =
+                || TodoStore::open(temp.path(), Some(backend), Access::Write, Duration::ZERO);
+            let mut store = open().unwrap();
+            let before = unix_now();
+            let added = store.add("/p", NewTodo::new("stamp")).unwrap();
+            let created = added.created_at.unwrap();
+            assert!(created >= before, "{backend}");
+            assert_eq!(added.updated_at, Some(created), "{backend}");
+            assert_eq!(added.completed_at, None, "{backend}");
+
+            let done = store.set_status("/p", Id(0), Status::Done, None).unwrap();
+            let completed = done.completed_at.unwrap();
+            assert!(completed >= created, "{backend}");
+            // finishing it again keeps the first stamp
+            let cancelled = store
+                .set_status("/p", Id(0), Status::Cancelled, None)
+                .unwrap();
+            assert_eq!(cancelled.completed_at, Some(completed), "{backend}");
+            drop(store);
+
+            let mut store = open().unwrap();
+            let stored = &store.list("/p").unwrap().unwrap()[0];
+            assert_eq!(stored.created_at, Some(created), "{backend}");
+            assert_eq!(stored.completed_at, Some(completed), "{backend}");
+            let reopened = store.set_status("/p", Id(0), Status::Active, None);
+            let reopened = reopened.unwrap();
+            assert_eq!(reopened.completed_at, None, "{backend}");
+            assert!(reopened.updated_at >= Some(completed), "{backend}");
+        }
+    }
+
+    #[test]
+    fn readers_cant_change_the_store() {
+        let temp = TempDir::new("store-read-only");
+        open(&temp, Access::Write)
+            .unwrap()
+            .add("/p", NewTodo::new("one"))
+            .unwrap();
+        let mut store = open(&temp, Access::Read).unwrap();
+        assert!(matches!(
+            store.add("/p", NewTodo::new("two")),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(store.undo(), Err(Error::ReadOnly)));
+        assert!(matches!(
+            store.delete("/p", Id(0), true, |_| true),
+            Err(Error::ReadOnly)
+        ));
+        assert_eq!(store.list("/p").unwrap().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn get_finds_todos_by_id_and_position() {
+        let temp = TempDir::new("store-get");
+        let mut store = open(&temp, Access::Write).unwrap();
+        assert!(matches!(store.get("/p", Id(0)), Err(Error::NotFound(_))));
+        store.add("/p", NewTodo::new("one")).unwrap();
+        store.add("/p", NewTodo::new("two")).unwrap();
+        assert_eq!(store.get("/p", Id(1)).unwrap().text, "two");
+        assert_eq!(store.get("/p", Position(1)).unwrap().text, "one");
+        assert!(matches!(store.get("/p", Id(2)), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn a_failed_delete_keeps_the_todo_archived() {
+        let temp = TempDir::new("store-delete-fails");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("keep")).unwrap();
+        // a directory where the new list would be written makes saving fail
+        let blocker = temp.path().join(format!("{}.new", todo_file_name("/p")));
+        std::fs::create_dir_all(blocker.join("x")).unwrap();
+        assert!(store.delete("/p", Id(0), true, |_| true).is_err());
+        assert_eq!(store.list("/p").unwrap().unwrap().len(), 1);
+        assert_eq!(store.archived().unwrap().len(), 1);
+        assert_eq!(store.history().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn restore_keeps_todos_it_cant_put_back() {
+        let temp = TempDir::new("store-restore-conflict");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("one")).unwrap();
+        store.delete("/p", Id(0), true, |_| true).unwrap();
+        store.undo().unwrap();
+        // an archived copy of a todo that is back in its list
+        let back = store.get("/p", Id(0)).unwrap();
+        store.archive("/p", back).unwrap();
+        assert!(matches!(store.restore("/p", 0), Err(Error::Conflict(_))));
+        assert_eq!(store.archived().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn purged_todos_leave_no_trace() {
+        let temp = TempDir::new("store-purge");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("keep")).unwrap();
+        store.add("/p", NewTodo::new("secret")).unwrap();
+        store.update("/p", Id(1), "more secret").unwrap();
+        store.update("/p", Id(0), "kept").unwrap();
+        store.delete("/p", Id(1), false, |_| true).unwrap();
+
+        let journal = std::fs::read_to_string(temp.path().join("journal.tsv")).unwrap();
+        assert!(!journal.contains("secret"), "{journal}");
+        assert_eq!(store.archived().unwrap(), []);
+        let ops: Vec<Operation> = store.history().unwrap().iter().map(|e| e.op).collect();
+        assert_eq!(ops.last(), Some(&Operation::Purge));
+
+        // undo passes over the purge and the purged todo's changes
+        assert_eq!(store.undo().unwrap().after.unwrap().text, "kept");
+        assert_eq!(store.undo().unwrap().after.unwrap().text, "keep");
+        assert!(matches!(store.undo(), Err(Error::NotFound(_))));
+        assert_eq!(store.list("/p").unwrap().unwrap(), []);
+        let journal = std::fs::read_to_string(temp.path().join("journal.tsv")).unwrap();
+        assert!(!journal.contains("secret"), "{journal}");
+    }
+
+    #[test]
+    fn add_fails_once_ids_run_out() {
+        let temp = TempDir::new("store-last-id");
+        let mut store = open(&temp, Access::Write).unwrap();
+        store.add("/p", NewTodo::new("first")).unwrap();
+        let list = TodoList::new(u64::MAX - 1, Vec::new());
+        store.storage.save("/p", &list).unwrap();
+        assert_eq!(
+            store.add("/p", NewTodo::new("last")).unwrap().id,
+            u64::MAX - 1
+        );
+        assert!(matches!(
+            store.add("/p", NewTodo::new("one more")),
+            Err(Error::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn fsck_handles_the_last_ids() {
+        use crate::Problem::*;
+        let temp = TempDir::new("store-fsck-last-id");
+        let file_name = todo_file_name("/a");
+        let dir_map = format!("#format\t2\n/a\t{file_name}\n");
+        std::fs::write(temp.path().join(DIR_MAP_NAME), dir_map).unwrap();
+        let list = "#format\t3\n18446744073709551614\tlast\t[ ]\n\
+            18446744073709551614\tsame\t[ ]\n18446744073709551615\tpast\t[ ]\n";
+        std::fs::write(temp.path().join(&file_name), list).unwrap();
+
+        let findings = TodoStore::fsck(temp.path(), None, Duration::ZERO, false).unwrap();
+        assert!(
+            matches!(
+                problems(&findings)[..],
+                [
+                    MalformedLine { line: 4, .. },
+                    DuplicateId {
+                        id: 18_446_744_073_709_551_614,
+                        ..
+                    }
+                ]
+            ),
+            "{findings:?}"
+        );
+
+        let findings = TodoStore::fsck(temp.path(), None, Duration::ZERO, true).unwrap();
+        assert!(findings.iter().all(|finding| finding.repair.is_some()));
+        let mut store = open(&temp, Access::Read).unwrap();
+        assert_eq!(store.list("/a").unwrap().unwrap()[0].text, "last");
+        let quarantined =
+            std::fs::read_to_string(temp.path().join("quarantine").join(&file_name)).unwrap();
+        assert!(quarantined.contains("past") && quarantined.contains("same"));
+    }
+}
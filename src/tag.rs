@@ -0,0 +1,46 @@
+//! Tags group todos across directories, `+tag` for what a todo is about and
+//! `@context` for where or with whom it gets done
+
+const TAG_PREFIX: char = '+';
+const CONTEXT_PREFIX: char = '@';
+
+/// The `+tag` and `@context` words of `text`
+pub fn tags_in(text: &str) -> impl Iterator<Item = &str> {
+    text.split_whitespace().filter(|word| is_tag(word))
+}
+
+/// `tag` as it is stored: with a `+` in front unless it is a `+tag` or
+/// `@context` already. `None` if it is empty or has whitespace.
+pub fn parse_tag(tag: &str) -> Option<String> {
+    if tag.is_empty() || tag.contains(char::is_whitespace) {
+        return None;
+    }
+    match is_tag(tag) {
+        true => Some(tag.to_string()),
+        false => Some(format!("{TAG_PREFIX}{tag}")),
+    }
+}
+
+fn is_tag(word: &str) -> bool {
+    word.len() > 1 && word.starts_with([TAG_PREFIX, CONTEXT_PREFIX])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_tags_and_contexts_in_text() {
+        let tags: Vec<&str> = tags_in("fix +bug @home, not a+b or + or @").collect();
+        assert_eq!(tags, ["+bug", "@home,"]);
+    }
+
+    #[test]
+    fn plain_tags_get_a_plus() {
+        assert_eq!(parse_tag("bug").as_deref(), Some("+bug"));
+        assert_eq!(parse_tag("+bug").as_deref(), Some("+bug"));
+        assert_eq!(parse_tag("@home").as_deref(), Some("@home"));
+        assert_eq!(parse_tag(""), None);
+        assert_eq!(parse_tag("two words"), None);
+    }
+}
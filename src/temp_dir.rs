@@ -0,0 +1,25 @@
+//! Scratch directories for tests that need a real store
+use std::path::{Path, PathBuf};
+
+/// An empty directory under the system temp dir, removed on drop
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` must be unique among the tests, they run in parallel
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("todo-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
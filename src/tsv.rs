@@ -0,0 +1,255 @@
+use std::{
+    fs::{copy, create_dir, remove_file, rename},
+    path::PathBuf,
+};
+
+use crate::{
+    Error, Result, TodoList,
+    dir_map::{DIR_MAP_FORMAT_VERSION, DIR_MAP_NAME, DirMap, todo_file_name},
+    files::{read_file, sync_dir, unix_now, unused_path, with_pushed, write_atomic},
+    record::{FORMAT_VERSION, format_list, list_format, parse_list},
+    storage::Storage,
+    store::Migration,
+};
+
+/// Where `retire` moves the TSV files, with the backup extension added
+const TSV_BACKUP_DIR_NAME: &str = "tsv";
+/// Extension of the directories `migrate` and `retire` back the store up to
+const BACKUP_EXT: &str = "bak";
+
+/// The default backend: `dirmap.tsv` maps each directory to the file holding
+/// its todos, one record per line.
+#[derive(Debug)]
+pub(crate) struct TsvStorage {
+    dir: PathBuf,
+    dir_map: DirMap,
+}
+
+impl TsvStorage {
+    pub(crate) fn open(mut dir: PathBuf) -> Result<Self> {
+        let dir_map_buf = read_file(&mut dir, DIR_MAP_NAME)?;
+        let dir_map = with_pushed(&mut dir, DIR_MAP_NAME, |path| {
+            DirMap::parse(&dir_map_buf, path)
+        })?;
+        Ok(Self { dir, dir_map })
+    }
+
+    /// Whether the store in `dir` has TSV data
+    pub(crate) fn exists(dir: &mut PathBuf) -> bool {
+        with_pushed(dir, DIR_MAP_NAME, |path| path.is_file())
+    }
+
+    fn load_file(&mut self, file_name: &str) -> Result<TodoList> {
+        let raw = read_file(&mut self.dir, file_name)?;
+        with_pushed(&mut self.dir, file_name, |path| parse_list(&raw, path))
+    }
+
+    /// Whether the dir map or a todo file is in an older format
+    fn needs_upgrade(&mut self) -> Result<bool> {
+        if self.dir_map.format < DIR_MAP_FORMAT_VERSION {
+            return Ok(true);
+        }
+        for file_name in self.file_names() {
+            if list_format(&read_file(&mut self.dir, &file_name)?) < FORMAT_VERSION {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Copies the dir map and todo files to `migrate-<unix time>.bak`
+    fn backup(&mut self) -> Result<PathBuf> {
+        let backup_dir = self
+            .dir
+            .join(format!("migrate-{}.{BACKUP_EXT}", unix_now()));
+        create_dir(&backup_dir).map_err(Error::io(&backup_dir))?;
+        let mut file_names = self.file_names();
+        file_names.push(DIR_MAP_NAME.to_string());
+        for file_name in file_names {
+            let path = with_pushed(&mut self.dir, &file_name, |path| path.to_path_buf());
+            if path.is_file() {
+                copy(&path, backup_dir.join(&file_name)).map_err(Error::io(&path))?;
+            }
+        }
+        Ok(backup_dir)
+    }
+
+    /// Renames todo files written with the old `DefaultHasher` names
+    fn rename_hashed_files(&mut self) -> Result<Vec<Migration>> {
+        let mut done = Vec::new();
+        for (key, file_name) in self.dir_map.iter_mut() {
+            let new_name = todo_file_name(key);
+            if *file_name == new_name {
+                continue;
+            }
+            let new_path = with_pushed(&mut self.dir, &new_name, |path| path.to_path_buf());
+            if new_path.exists() {
+                done.push(Migration::Conflict {
+                    dir: key.to_string(),
+                    existing: new_path,
+                });
+                continue;
+            }
+            let old_path = with_pushed(&mut self.dir, &*file_name, |path| path.to_path_buf());
+            if old_path.is_file() {
+                rename(&old_path, &new_path).map_err(Error::io(&old_path))?;
+            }
+            done.push(Migration::Renamed {
+                dir: key.to_string(),
+                from: std::mem::replace(file_name, new_name.clone()),
+                to: new_name,
+            });
+        }
+        if done.iter().any(|m| matches!(m, Migration::Renamed { .. })) {
+            self.save_dir_map()?;
+        }
+        Ok(done)
+    }
+
+    /// Rewrites the dir map and todo files in older formats
+    fn upgrade_formats(&mut self) -> Result<Vec<Migration>> {
+        let mut done = Vec::new();
+        for file_name in self.file_names() {
+            let raw = read_file(&mut self.dir, &file_name)?;
+            let from = list_format(&raw);
+            if from >= FORMAT_VERSION {
+                continue;
+            }
+            let path = with_pushed(&mut self.dir, &file_name, |path| path.to_path_buf());
+            let list = parse_list(&raw, &path)?;
+            write_atomic(&mut self.dir, &file_name, format_list(&list).as_bytes())?;
+            done.push(Migration::Upgraded {
+                file: path,
+                from,
+                to: FORMAT_VERSION,
+            });
+        }
+        if self.dir_map.format < DIR_MAP_FORMAT_VERSION {
+            self.save_dir_map()?;
+            done.push(Migration::Upgraded {
+                file: self.dir.join(DIR_MAP_NAME),
+                from: self.dir_map.format,
+                to: DIR_MAP_FORMAT_VERSION,
+            });
+            self.dir_map.format = DIR_MAP_FORMAT_VERSION;
+        }
+        Ok(done)
+    }
+
+    /// Names of the todo files the dir map points at
+    fn file_names(&self) -> Vec<String> {
+        self.dir_map
+            .iter()
+            .map(|(_dir, file_name)| file_name.to_string())
+            .collect()
+    }
+
+    fn save_dir_map(&mut self) -> Result<()> {
+        write_atomic(
+            &mut self.dir,
+            DIR_MAP_NAME,
+            self.dir_map.to_tsv().as_bytes(),
+        )
+    }
+}
+
+impl Storage for TsvStorage {
+    fn dirs(&mut self) -> Result<Vec<String>> {
+        Ok(self
+            .dir_map
+            .iter()
+            .map(|(dir, _)| dir.to_string())
+            .collect())
+    }
+
+    fn load(&mut self, dir: &str) -> Result<Option<TodoList>> {
+        match self.dir_map.file_name(dir) {
+            Some(file_name) => {
+                let file_name = file_name.to_string();
+                self.load_file(&file_name).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn save(&mut self, dir: &str, list: &TodoList) -> Result<()> {
+        let (file_name, new_entry) = match self.dir_map.file_name(dir) {
+            Some(file_name) => (file_name.to_string(), false),
+            None => (todo_file_name(dir), true),
+        };
+        write_atomic(&mut self.dir, &file_name, format_list(list).as_bytes())?;
+        if new_entry {
+            self.dir_map.insert(dir, file_name);
+            self.save_dir_map()?;
+        }
+        Ok(())
+    }
+
+    /// Drops the dir map entry, then the file, so a crash can at worst leave
+    /// a file nothing points at
+    fn remove(&mut self, dir: &str) -> Result<()> {
+        let Some(file_name) = self.dir_map.remove(dir) else {
+            return Ok(());
+        };
+        self.save_dir_map()?;
+        with_pushed(&mut self.dir, &file_name, |path| match remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::io(path)(e)),
+            _ => Ok(()),
+        })
+    }
+
+    /// Renames the todo file to the name derived from `to`, unless that is
+    /// taken, then points the dir map at it
+    fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let Some(old_name) = self.dir_map.file_name(from).map(str::to_string) else {
+            return Ok(());
+        };
+        let mut new_name = todo_file_name(to);
+        let old_path = with_pushed(&mut self.dir, &old_name, |path| path.to_path_buf());
+        let new_path = with_pushed(&mut self.dir, &new_name, |path| path.to_path_buf());
+        if new_path.exists() || !old_path.is_file() {
+            new_name = old_name;
+        } else {
+            rename(&old_path, &new_path).map_err(Error::io(&old_path))?;
+        }
+        self.dir_map.rekey(from, to, new_name);
+        self.save_dir_map()
+    }
+
+    /// Copies the dir map and every todo file to a backup directory, then
+    /// renames todo files written with the old `DefaultHasher` names to their
+    /// current name and rewrites files in older formats in the current one.
+    fn migrate(&mut self) -> Result<Vec<Migration>> {
+        let mut done = Vec::new();
+        if self.needs_upgrade()? {
+            done.push(Migration::BackedUp(self.backup()?));
+        }
+        done.extend(self.rename_hashed_files()?);
+        done.extend(self.upgrade_formats()?);
+        Ok(done)
+    }
+
+    fn retire(mut self: Box<Self>) -> Result<PathBuf> {
+        let backup_dir = with_pushed(&mut self.dir, TSV_BACKUP_DIR_NAME, |path| {
+            unused_path(path, BACKUP_EXT)
+        });
+        create_dir(&backup_dir).map_err(Error::io(&backup_dir))?;
+        let mut backup_path = backup_dir.clone();
+        let file_names = self
+            .dir_map
+            .iter()
+            .map(|(_dir, file_name)| file_name)
+            .chain([DIR_MAP_NAME]);
+        for file_name in file_names {
+            let path = with_pushed(&mut self.dir, file_name, |path| path.to_path_buf());
+            if !path.exists() {
+                continue;
+            }
+            with_pushed(&mut backup_path, file_name, |to| {
+                rename(&path, to).map_err(Error::io(&path))
+            })?;
+        }
+        sync_dir(&self.dir).map_err(Error::io(&self.dir))?;
+        Ok(backup_dir)
+    }
+}